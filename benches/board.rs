@@ -0,0 +1,146 @@
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
+
+use tetris::game_board::GameBoard;
+use tetris::tetris_piece::{TetrisPiece, TetrisPieceType};
+
+// The Vec<i32> board the bitboard GameBoard replaced, kept to compare against.
+#[derive(Clone)]
+struct VecBoard {
+    grid: Vec<i32>,
+    width: i32,
+    height: i32,
+}
+
+impl VecBoard {
+    fn test_placement(&self, piece: &TetrisPiece) -> bool {
+        let grid = piece.grid();
+        for x in 0..5 {
+            for y in 0..5 {
+                if grid[(x + y * 5) as usize] != 0 {
+                    if (piece.x + x) >= self.width || (piece.y + y) >= self.height {
+                        return false;
+                    }
+                    if (piece.x + x) < 0 || (piece.y + y) < 0 {
+                        return false;
+                    }
+                    if self.grid[((piece.x + x) + (piece.y + y) * self.width) as usize] != 0 {
+                        return false;
+                    }
+                }
+            }
+        }
+        true
+    }
+
+    fn full_lines(&self) -> Vec<i32> {
+        let mut full_lines: Vec<i32> = Vec::new();
+        for y in 0..self.height {
+            if (0..self.width).all(|x| self.grid[(x + y * self.width) as usize] != 0) {
+                full_lines.push(y);
+            }
+        }
+        full_lines
+    }
+
+    fn clear_line(&mut self, line: i32) {
+        for y in (0..line).rev() {
+            for x in 0..self.width {
+                self.grid[(x + (y + 1) * self.width) as usize] =
+                    self.grid[(x + y * self.width) as usize];
+            }
+        }
+        for x in 0..self.width {
+            self.grid[x as usize] = 0;
+        }
+    }
+}
+
+// A 10x22 stack with a diagonal of holes, so that no row is full.
+fn stack() -> Vec<i32> {
+    let mut grid = vec![0; 10 * 22];
+    for (i, cell) in grid.iter_mut().enumerate().skip(120) {
+        *cell = if i % 10 == i / 10 % 10 { 0 } else { 1 };
+    }
+    grid
+}
+
+// Every piece in every rotation at a spread of positions, some of them out of bounds.
+fn pieces() -> Vec<TetrisPiece> {
+    (0..7 * 4 * 8)
+        .map(|i| {
+            let mut t = TetrisPiece::new(match i % 7 {
+                0 => TetrisPieceType::I,
+                1 => TetrisPieceType::O,
+                2 => TetrisPieceType::T,
+                3 => TetrisPieceType::S,
+                4 => TetrisPieceType::Z,
+                5 => TetrisPieceType::J,
+                _ => TetrisPieceType::L,
+            });
+            t.rotation = (i / 7 % 4) as u8;
+            t.x = i / 28 - 1;
+            t.y = i / 28 * 2;
+            t
+        })
+        .collect()
+}
+
+fn bench_board(c: &mut Criterion) {
+    let board = GameBoard::from_grid(10, 22, stack());
+    let vec_board = VecBoard {
+        grid: stack(),
+        width: 10,
+        height: 22,
+    };
+    let pieces = pieces();
+
+    c.bench_function("bitboard test_placement", |b| {
+        b.iter(|| {
+            for t in &pieces {
+                black_box(board.test_placement(black_box(t)));
+            }
+        })
+    });
+    c.bench_function("vec test_placement", |b| {
+        b.iter(|| {
+            for t in &pieces {
+                black_box(vec_board.test_placement(black_box(t)));
+            }
+        })
+    });
+    c.bench_function("bitboard full_lines", |b| {
+        b.iter(|| black_box(&board).full_lines())
+    });
+    c.bench_function("vec full_lines", |b| {
+        b.iter(|| black_box(&vec_board).full_lines())
+    });
+
+    // Clearing changes the board, so each run starts from a copy with a full bottom row.
+    let mut full = board.clone();
+    let mut vec_full = vec_board.clone();
+    for x in 0..10 {
+        full.set_cell(x, 21, 1);
+        vec_full.grid[(x + 21 * 10) as usize] = 1;
+    }
+    c.bench_function("bitboard clear_lines", |b| {
+        b.iter_batched_ref(
+            || full.clone(),
+            |board| board.clear_lines(),
+            BatchSize::SmallInput,
+        )
+    });
+    c.bench_function("vec clear_lines", |b| {
+        b.iter_batched_ref(
+            || vec_full.clone(),
+            |board| {
+                for line in board.full_lines() {
+                    board.clear_line(line);
+                }
+            },
+            BatchSize::SmallInput,
+        )
+    });
+}
+
+criterion_group!(benches, bench_board);
+criterion_main!(benches);
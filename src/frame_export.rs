@@ -0,0 +1,147 @@
+use gif::{Encoder, Frame, Repeat};
+use sdl2::image::SaveSurface;
+use sdl2::surface::Surface;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::tetris_game::TICKS_PER_SECOND;
+
+pub const DEFAULT_FPS: u32 = 30;
+
+// Quality of the GIF palette, 1 is the best and slowest, 30 the fastest.
+const GIF_SPEED: i32 = 10;
+
+// Game tick shown in a frame of a clip, frames are spread evenly over the game's ticks.
+pub fn frame_tick(frame: u32, fps: u32) -> u32 {
+    (frame as u64 * TICKS_PER_SECOND as u64 / fps as u64) as u32
+}
+
+// GIF delays are in hundredths of a second, rounding where each frame starts rather than
+// every delay keeps a clip from drifting at frame rates that don't divide 100.
+fn gif_delay(frame: u32, fps: u32) -> u16 {
+    let start = |frame: u32| frame as u64 * 100 / fps as u64;
+    (start(frame + 1) - start(frame)) as u16
+}
+
+// Writes rendered frames out as numbered PNGs in a directory, and as an animated GIF.
+pub struct FrameExport {
+    dir: Option<PathBuf>,
+    gif: Option<Encoder<BufWriter<File>>>,
+    fps: u32,
+    width: u32,
+    height: u32,
+    pub frames: u32,
+}
+
+impl FrameExport {
+    pub fn new(
+        dir: Option<PathBuf>,
+        gif_path: Option<&Path>,
+        fps: u32,
+        width: u32,
+        height: u32,
+    ) -> Result<FrameExport, String> {
+        if let Some(dir) = &dir {
+            fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+        }
+        let gif = match gif_path {
+            Some(path) => {
+                let file = File::create(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+                let mut encoder =
+                    Encoder::new(BufWriter::new(file), width as u16, height as u16, &[])
+                        .map_err(|e| e.to_string())?;
+                encoder
+                    .set_repeat(Repeat::Infinite)
+                    .map_err(|e| e.to_string())?;
+                Some(encoder)
+            }
+            None => None,
+        };
+        Ok(FrameExport {
+            dir,
+            gif,
+            fps,
+            width,
+            height,
+            frames: 0,
+        })
+    }
+
+    // Adds a frame the size the export was made for, in the RGB24 format.
+    pub fn add(&mut self, surface: &Surface) -> Result<(), String> {
+        if let Some(dir) = &self.dir {
+            surface.save(dir.join(format!("{:05}.png", self.frames)))?;
+        }
+        if self.gif.is_some() {
+            // Rows of the surface can be padded, the GIF wants the bare pixels.
+            let row_bytes = self.width as usize * 3;
+            let pitch = surface.pitch() as usize;
+            let rgb: Vec<u8> = surface.with_lock(|pixels| {
+                pixels
+                    .chunks(pitch)
+                    .take(self.height as usize)
+                    .flat_map(|row| &row[..row_bytes])
+                    .copied()
+                    .collect()
+            });
+            self.write_gif_frame(&rgb)?;
+        }
+        self.frames += 1;
+        Ok(())
+    }
+
+    fn write_gif_frame(&mut self, rgb: &[u8]) -> Result<(), String> {
+        let Some(encoder) = &mut self.gif else {
+            return Ok(());
+        };
+        let mut frame =
+            Frame::from_rgb_speed(self.width as u16, self.height as u16, rgb, GIF_SPEED);
+        frame.delay = gif_delay(self.frames, self.fps);
+        encoder.write_frame(&frame).map_err(|e| e.to_string())
+    }
+
+    // Ends the GIF, which is only complete once its trailer is written.
+    pub fn finish(self) -> Result<(), String> {
+        if let Some(encoder) = self.gif {
+            encoder
+                .into_inner()
+                .and_then(|mut writer| writer.flush())
+                .map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame_timing() {
+        assert_eq!(frame_tick(0, 30), 0);
+        assert_eq!(frame_tick(30, 30), TICKS_PER_SECOND);
+        assert_eq!(frame_tick(1, 50), 4);
+        for fps in [1, 7, 24, 30, 50] {
+            let total: u32 = (0..fps).map(|frame| gif_delay(frame, fps) as u32).sum();
+            assert_eq!(total, 100);
+        }
+        assert_eq!(gif_delay(0, 30), 3);
+        assert_eq!(gif_delay(2, 30), 4);
+    }
+
+    #[test]
+    fn test_gif_export() {
+        let path = std::env::temp_dir().join("tetris_test_export.gif");
+        let mut export = FrameExport::new(None, Some(&path), 10, 4, 2).unwrap();
+        for shade in [0, 128, 255] {
+            export.write_gif_frame(&[shade; 4 * 2 * 3]).unwrap();
+            export.frames += 1;
+        }
+        export.finish().unwrap();
+        let gif = fs::read(&path).unwrap();
+        assert!(gif.starts_with(b"GIF89a"));
+        assert_eq!(gif.last(), Some(&0x3b));
+        fs::remove_file(&path).unwrap();
+    }
+}
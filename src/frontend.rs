@@ -0,0 +1,445 @@
+use std::time::{Duration, Instant};
+
+use crate::fumen::fumen_url;
+use crate::game_mode::format_time;
+use crate::high_scores::Record;
+use crate::replay::{Replay, ReplayPlayer};
+#[cfg(feature = "sdl")]
+use crate::software_renderer::SoftwareRenderer;
+use crate::tetris_game::{Input, StackVisibility, TetrisGame, TICKS_PER_SECOND, TICK_MS};
+use crate::tetris_piece::TetrisPieceType;
+
+// How long the revealed stack stays on screen after a game with a hidden stack ends.
+const REVEAL_MS: u64 = 3000;
+
+// How far seeking jumps when watching a replay.
+const SEEK_TICKS: u32 = 5 * TICKS_PER_SECOND;
+
+// What a frontend asks the session to do, mostly engine inputs.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Command {
+    Input(Input),
+    Quit,
+    ExportFumen, // tell the fumen link of the board as it is
+    Snapshot,    // save a picture of the game
+    // Replay playback
+    TogglePause,
+    Step,
+    ToggleFastForward,
+    Seek(i32), // jump SEEK_TICKS back for -1 or ahead for 1
+}
+
+// A key the game reacts to, whichever frontend it was pressed in. Each frontend turns its
+// own key codes into these, so the bindings below are the same everywhere.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Key {
+    Left,
+    Right,
+    Down,
+    Escape,
+    F12,
+    Char(char), // letters in lower case
+}
+
+// Keys that aren't engine inputs: Esc quits, E tells the fumen link of the board and F12
+// saves a picture of the game. Watching a replay, space pauses, `.` steps a single tick
+// while paused, F toggles fast forward and left and right jump back and ahead.
+pub fn key_command(key: Key, replaying: bool) -> Option<Command> {
+    let command = match key {
+        Key::Escape => Command::Quit,
+        Key::Char('e') => Command::ExportFumen,
+        Key::F12 => Command::Snapshot,
+        _ if !replaying => return None,
+        Key::Char(' ') => Command::TogglePause,
+        Key::Char('.') => Command::Step,
+        Key::Char('f') => Command::ToggleFastForward,
+        Key::Left => Command::Seek(-1),
+        Key::Right => Command::Seek(1),
+        _ => return None,
+    };
+    Some(command)
+}
+
+// Which way a key shifts the piece. Frontends time shifting themselves, as they learn
+// about a key being let go in different ways.
+pub fn shift_direction(key: Key) -> Option<i32> {
+    match key {
+        Key::Left => Some(-1),
+        Key::Right => Some(1),
+        _ => None,
+    }
+}
+
+// The engine input for pressing a key other than a shift key, if it does anything in this
+// game.
+pub fn key_input(key: Key, game: &TetrisGame) -> Option<Input> {
+    let Key::Char(c) = key else {
+        return (key == Key::Down).then_some(Input::SoftDrop);
+    };
+    let input = match c {
+        'd' => Input::Rotate(true),
+        'a' => Input::Rotate(false),
+        'q' => Input::Hold,
+        ' ' => Input::HardDrop,
+        // Practice keys: undo, redo and picking the next piece by hand.
+        'z' if game.mode.allows_undo() => Input::Undo,
+        'x' if game.mode.allows_undo() => Input::Redo,
+        'm' if game.mode.allows_undo() => match game.chosen_next() {
+            Some(_) => Input::ChooseNext(None),
+            None => Input::ChooseNext(Some(game.next.piece_type)),
+        },
+        '1'..='7' if game.chosen_next().is_some() => Input::ChooseNext(
+            TetrisPieceType::from_letter("IOTSZJL".chars().nth(c as usize - '1' as usize)?),
+        ),
+        _ => return None,
+    };
+    Some(input)
+}
+
+// What a renderer gets to draw: the game, which it can't change, and the stats panel.
+pub struct GameView<'a> {
+    pub game: &'a TetrisGame,
+    pub stats: &'a [String],
+}
+
+pub trait Renderer {
+    fn render(&mut self, view: &GameView) -> Result<(), String>;
+
+    // Tells the player about something that happened, such as why the game ended.
+    fn message(&mut self, text: &str) {
+        println!("{}", text);
+    }
+
+    // How long to wait between two frames.
+    fn frame_time(&self) -> Duration {
+        Duration::from_millis(1)
+    }
+}
+
+pub trait InputSource {
+    // The commands that came in since the last frame.
+    fn poll(&mut self, game: &TetrisGame) -> Result<Vec<Command>, String>;
+}
+
+// A game being played or a replay being watched, run by whichever frontend draws it and
+// takes the input. The rules stay with the engine, the session only passes commands on
+// and runs the engine at its fixed rate.
+pub struct Session {
+    pub game: TetrisGame,
+    pub recording: Replay,
+    pub player: Option<ReplayPlayer>,
+    best: Option<Record>,
+    tick_accumulator: Duration,
+    game_over_for: Option<Duration>,
+    replay_checked: bool,
+}
+
+impl Session {
+    pub fn new(
+        game: TetrisGame,
+        recording: Replay,
+        player: Option<ReplayPlayer>,
+        best: Option<Record>,
+    ) -> Session {
+        Session {
+            game,
+            recording,
+            player,
+            best,
+            tick_accumulator: Duration::ZERO,
+            game_over_for: None,
+            replay_checked: false,
+        }
+    }
+
+    pub fn stats(&self) -> Vec<String> {
+        let mut stats = self.game.mode.stats(&self.game, self.best.as_ref());
+        if let Some(player) = &self.player {
+            stats.push(match (player.paused, player.speed) {
+                (true, _) => "PAUSED".to_string(),
+                (false, 1) => "REPLAY".to_string(),
+                (false, speed) => format!("REPLAY x{}", speed),
+            });
+        }
+        stats
+    }
+
+    pub fn view<'a>(&'a self, stats: &'a [String]) -> GameView<'a> {
+        GameView {
+            game: &self.game,
+            stats,
+        }
+    }
+
+    fn command(&mut self, command: Command, renderer: &mut dyn Renderer) {
+        let game = &mut self.game;
+        match (command, &mut self.player) {
+            (Command::Input(input), None) => self.recording.record(game, input),
+            (Command::ExportFumen, _) => {
+                let piece =
+                    (!game.is_spawning() && game.game_over.is_none()).then_some(&game.piece);
+                match fumen_url(&game.board, piece) {
+                    Ok(url) => renderer.message(&url),
+                    Err(e) => renderer.message(&format!("Can't export the board: {}", e)),
+                }
+            }
+            #[cfg(feature = "sdl")]
+            (Command::Snapshot, _) => {
+                let path = SoftwareRenderer::snapshot_path(game);
+                let stats = self.stats();
+                match SoftwareRenderer::load()
+                    .and_then(|mut software| software.save_png(&self.game, &stats, &path))
+                {
+                    Ok(()) => renderer.message(&format!("Saved {}", path.display())),
+                    Err(e) => renderer.message(&format!("Can't save a snapshot: {}", e)),
+                }
+            }
+            #[cfg(not(feature = "sdl"))]
+            (Command::Snapshot, _) => renderer.message("This build can't save pictures"),
+            (Command::TogglePause, Some(player)) => player.paused = !player.paused,
+            (Command::Step, Some(player)) if player.paused => player.step(game),
+            (Command::ToggleFastForward, Some(player)) => {
+                player.speed = if player.speed == 1 { 4 } else { 1 }
+            }
+            (Command::Seek(direction), Some(player)) => {
+                let tick = if direction < 0 {
+                    game.ticks.saturating_sub(SEEK_TICKS)
+                } else {
+                    game.ticks + SEEK_TICKS
+                };
+                player.seek(game, tick);
+            }
+            _ => {}
+        }
+    }
+
+    // Runs a frame: takes the commands that came in, runs the engine for the time that
+    // passed and draws the game. Returns whether the session carries on.
+    pub fn frame(
+        &mut self,
+        elapsed: Duration,
+        input: &mut dyn InputSource,
+        renderer: &mut dyn Renderer,
+    ) -> Result<bool, String> {
+        for command in input.poll(&self.game)? {
+            if command == Command::Quit {
+                return Ok(false);
+            }
+            self.command(command, renderer);
+        }
+
+        // Run the engine at its fixed rate no matter how long a frame took, but don't
+        // try to catch up on more than a quarter of a second.
+        let tick = Duration::from_millis(TICK_MS as u64);
+        self.tick_accumulator = (self.tick_accumulator + elapsed).min(Duration::from_millis(250));
+        while self.tick_accumulator >= tick {
+            match &mut self.player {
+                Some(player) if !player.paused => {
+                    for _ in 0..player.speed {
+                        player.step(&mut self.game);
+                    }
+                }
+                Some(_) => {}
+                None => self.game.tick(),
+            }
+            self.tick_accumulator -= tick;
+        }
+        if let Some(player) = &mut self.player {
+            // A replay stays on screen at its end, so it can still be wound back.
+            if player.is_finished(&self.game) && !self.replay_checked {
+                self.replay_checked = true;
+                let message = match player.verify(&mut self.game) {
+                    Ok(()) => format!(
+                        "Replay verified: score {}, {} lines in {}",
+                        self.game.score,
+                        self.game.lines,
+                        format_time(self.game.elapsed_ms())
+                    ),
+                    Err(e) => format!("Replay does not match: {}", e),
+                };
+                renderer.message(&message);
+            }
+        } else if let Some(reason) = self.game.game_over {
+            let shown_for = match self.game_over_for {
+                Some(shown_for) => shown_for + elapsed,
+                None => {
+                    renderer.message(&format!("Game over: {} (seed {})", reason, self.game.seed));
+                    Duration::ZERO
+                }
+            };
+            self.game_over_for = Some(shown_for);
+            if self.game.visibility == StackVisibility::Visible
+                || shown_for >= Duration::from_millis(REVEAL_MS)
+            {
+                return Ok(false);
+            }
+        }
+
+        let stats = self.stats();
+        renderer.render(&self.view(&stats))?;
+        Ok(true)
+    }
+
+    // Runs frames in real time until the game ends or the player quits.
+    pub fn run(
+        &mut self,
+        input: &mut dyn InputSource,
+        renderer: &mut dyn Renderer,
+    ) -> Result<(), String> {
+        let mut last_frame = Instant::now();
+        loop {
+            let now = Instant::now();
+            if !self.frame(now - last_frame, input, renderer)? {
+                return Ok(());
+            }
+            last_frame = now;
+            std::thread::sleep(renderer.frame_time());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game_board::GameBoard;
+    use crate::game_mode::GameMode;
+    use crate::tetris_piece::TetrisPieceState;
+    use std::collections::VecDeque;
+
+    // Hands out the commands of one frame at a time.
+    struct ScriptedInput(VecDeque<Vec<Command>>);
+
+    impl InputSource for ScriptedInput {
+        fn poll(&mut self, _: &TetrisGame) -> Result<Vec<Command>, String> {
+            Ok(self.0.pop_front().unwrap_or_default())
+        }
+    }
+
+    // Keeps what it was asked to draw instead of drawing it.
+    #[derive(Default)]
+    struct TestRenderer {
+        frames: Vec<(u32, Vec<String>)>,
+        messages: Vec<String>,
+    }
+
+    impl Renderer for TestRenderer {
+        fn render(&mut self, view: &GameView) -> Result<(), String> {
+            self.frames.push((view.game.ticks, view.stats.to_vec()));
+            Ok(())
+        }
+
+        fn message(&mut self, text: &str) {
+            self.messages.push(text.to_string());
+        }
+    }
+
+    fn new_game() -> TetrisGame {
+        TetrisGame::new(
+            GameBoard::with_hidden_rows(10, 20, 20),
+            GameMode::Endless,
+            5,
+        )
+    }
+
+    #[test]
+    fn test_session_plays_and_records() {
+        let mut session = Session::new(new_game(), Replay::default(), None, None);
+        let mut input = ScriptedInput(VecDeque::from([
+            vec![],
+            vec![Command::Input(Input::Shift(-1))],
+            vec![
+                Command::Input(Input::Shift(0)),
+                Command::Input(Input::HardDrop),
+            ],
+            vec![Command::Quit],
+        ]));
+        let mut renderer = TestRenderer::default();
+        let frame = Duration::from_millis(100);
+        while session.frame(frame, &mut input, &mut renderer).unwrap() {}
+        assert_eq!(renderer.frames.len(), 3);
+        assert_eq!(renderer.frames[2].0, 60);
+        assert_eq!(renderer.frames[0].1, vec!["LINES 0".to_string()]);
+        assert_eq!(
+            session.recording.inputs,
+            vec![
+                (20, Input::Shift(-1)),
+                (40, Input::Shift(0)),
+                (40, Input::HardDrop)
+            ]
+        );
+        // The hard drop landed the piece, it locks once its lock delay runs out.
+        assert_eq!(session.game.piece.state, TetrisPieceState::Placing);
+    }
+
+    #[test]
+    fn test_session_ends_with_the_game() {
+        let mut session = Session::new(new_game(), Replay::default(), None, None);
+        let mut input = ScriptedInput(VecDeque::new());
+        let mut renderer = TestRenderer::default();
+        while session
+            .frame(Duration::from_millis(20), &mut input, &mut renderer)
+            .unwrap()
+        {
+            session.game.apply(Input::HardDrop);
+        }
+        assert!(session.game.game_over.is_some());
+        assert!(renderer.messages[0].starts_with("Game over"));
+    }
+
+    #[test]
+    fn test_key_bindings() {
+        let game = new_game();
+        assert_eq!(key_input(Key::Char(' '), &game), Some(Input::HardDrop));
+        assert_eq!(key_input(Key::Down, &game), Some(Input::SoftDrop));
+        assert_eq!(key_input(Key::Char('z'), &game), None);
+        let zen = TetrisGame::new(
+            GameBoard::with_hidden_rows(10, 20, 20),
+            GameMode::Zen { gravity: 0 },
+            5,
+        );
+        assert_eq!(key_input(Key::Char('z'), &zen), Some(Input::Undo));
+        assert_eq!(key_command(Key::Escape, false), Some(Command::Quit));
+        assert_eq!(key_command(Key::Char(' '), false), None);
+        assert_eq!(
+            key_command(Key::Char(' '), true),
+            Some(Command::TogglePause)
+        );
+        assert_eq!(key_command(Key::Left, true), Some(Command::Seek(-1)));
+        assert_eq!(shift_direction(Key::Left), Some(-1));
+        assert_eq!(shift_direction(Key::Char('a')), None);
+    }
+
+    #[test]
+    fn test_session_watches_replay() {
+        let mut game = new_game();
+        let mut recording = Replay::default();
+        for _ in 0..3 {
+            recording.record(&mut game, Input::HardDrop);
+            for _ in 0..100 {
+                game.tick();
+            }
+        }
+        recording.finish(&game);
+
+        let player = ReplayPlayer::new(recording, &new_game());
+        let mut session = Session::new(new_game(), Replay::default(), Some(player), None);
+        let mut input = ScriptedInput(VecDeque::from([
+            vec![Command::TogglePause],
+            vec![Command::Step, Command::Input(Input::HardDrop)],
+            vec![Command::TogglePause, Command::ToggleFastForward],
+        ]));
+        let mut renderer = TestRenderer::default();
+        let frame = Duration::from_millis(50);
+        for _ in 0..20 {
+            assert!(session.frame(frame, &mut input, &mut renderer).unwrap());
+        }
+        assert_eq!(renderer.frames[0].1.last().unwrap(), "PAUSED");
+        assert_eq!(renderer.frames[1].0, 1);
+        assert_eq!(renderer.frames[2].1.last().unwrap(), "REPLAY x4");
+        assert!(session.recording.inputs.is_empty());
+        assert_eq!(
+            renderer.messages,
+            vec!["Replay verified: score 0, 0 lines in 0:01.500"]
+        );
+    }
+}
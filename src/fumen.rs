@@ -0,0 +1,706 @@
+use crate::game_board::GameBoard;
+use crate::tetris_piece::{TetrisPiece, TetrisPieceType};
+
+// Fumen is the board editor setups are usually shared with, as links like
+// `https://fumen.zui.jp/?v115@vhAAgH`. Only the v115 format is supported.
+pub const FUMEN_URL: &str = "https://fumen.zui.jp/?";
+const PREFIXES: [&str; 3] = ["v115@", "m115@", "d115@"];
+
+// Fumen fields are 10 wide and 23 high, with one more row below for rising garbage.
+pub const FIELD_WIDTH: i32 = 10;
+pub const FIELD_HEIGHT: i32 = 23;
+const FIELD_BLOCKS: usize = (FIELD_WIDTH * (FIELD_HEIGHT + 1)) as usize;
+
+const ENCODE_TABLE: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+const COMMENT_CHARS: u32 = 96; // comments are printable ASCII from the space on, packed 4 to 5 digits
+
+// Flags of a page's action. A locked page's field rises or is mirrored once its piece
+// is put in, colorize is set on the first page and the comment follows the action.
+const RISE: u32 = 1;
+const MIRROR: u32 = 2;
+const COLORIZE: u32 = 4;
+const COMMENT: u32 = 8;
+const NO_LOCK: u32 = 16;
+
+// A field run that leaves every cell as it was, followed by how many more pages repeat it.
+const UNCHANGED_FIELD: u32 = 8 * FIELD_BLOCKS as u32 + FIELD_BLOCKS as u32 - 1;
+
+// Fumen numbers its blocks I, L, O, Z, T, J, S, gray, the board I, O, T, S, Z, J, L, garbage.
+const TO_FUMEN: [i32; 9] = [0, 1, 3, 5, 7, 4, 6, 2, 8];
+const FROM_FUMEN: [i32; 9] = [0, 1, 7, 2, 5, 3, 6, 4, 8];
+
+// Cells of every piece in spawn orientation around the point fumen positions it by, y up.
+const FUMEN_BLOCKS: [[(i32, i32); 4]; 7] = [
+    [(0, 0), (-1, 0), (1, 0), (2, 0)],  // I
+    [(0, 0), (1, 0), (0, 1), (1, 1)],   // O
+    [(0, 0), (-1, 0), (1, 0), (0, 1)],  // T
+    [(0, 0), (-1, 0), (0, 1), (1, 1)],  // S
+    [(0, 0), (1, 0), (0, 1), (-1, 1)],  // Z
+    [(0, 0), (-1, 0), (1, 0), (-1, 1)], // J
+    [(0, 0), (-1, 0), (1, 0), (1, 1)],  // L
+];
+
+// A field with the rising garbage row below it, fumen block ids, top row first.
+type Field = [i32; FIELD_BLOCKS];
+
+// One page of a fumen: a field, optionally a piece on it, and a comment.
+#[derive(Debug, Clone)]
+pub struct FumenPage {
+    pub board: GameBoard,           // FIELD_WIDTH x FIELD_HEIGHT
+    pub piece: Option<TetrisPiece>, // placed on `board`
+    pub lock: bool,                 // the piece is put into the field of the next page
+    pub comment: String,
+}
+
+impl FumenPage {
+    // A page showing the bottom FIELD_HEIGHT rows of a board, the piece moves along with them.
+    pub fn from_board(board: &GameBoard, piece: Option<&TetrisPiece>) -> Result<FumenPage, String> {
+        let mut page = FumenPage {
+            board: GameBoard::new(FIELD_WIDTH, FIELD_HEIGHT),
+            piece: piece.copied(),
+            lock: true,
+            comment: String::new(),
+        };
+        copy_bottom_rows(board, &mut page.board)?;
+        if let Some(piece) = &mut page.piece {
+            piece.y -= board.height - FIELD_HEIGHT;
+            if piece.cells().any(|(_, y)| piece.y + y < 0) {
+                return Err("the piece is above the fumen field".to_string());
+            }
+        }
+        Ok(page)
+    }
+
+    // Puts the page's field at the bottom of a board of the same width.
+    pub fn fill_board(&self, board: &mut GameBoard) -> Result<(), String> {
+        copy_bottom_rows(&self.board, board)
+    }
+}
+
+fn copy_bottom_rows(from: &GameBoard, to: &mut GameBoard) -> Result<(), String> {
+    if from.width != to.width {
+        return Err(format!("fumen fields are {} wide", FIELD_WIDTH));
+    }
+    for row in 0..from.height {
+        let y = to.height - from.height + row;
+        for x in 0..from.width {
+            match from.cell(x, row) {
+                0 if y < 0 => {}
+                _ if y < 0 => return Err("the blocks don't fit on the board".to_string()),
+                id => to.set_cell(x, y, id),
+            }
+        }
+    }
+    Ok(())
+}
+
+// The link to the fumen editor for a board and the piece on it.
+pub fn fumen_url(board: &GameBoard, piece: Option<&TetrisPiece>) -> Result<String, String> {
+    let page = FumenPage::from_board(board, piece)?;
+    Ok(format!("{}{}", FUMEN_URL, encode(&[page])?))
+}
+
+// Reads a fumen string or link. A page's field is what the previous pages leave behind,
+// with their locked pieces put in, lines cleared and garbage risen.
+pub fn decode(text: &str) -> Result<Vec<FumenPage>, String> {
+    let data = PREFIXES
+        .iter()
+        .find_map(|prefix| text.split_once(prefix).map(|(_, data)| data))
+        .ok_or("not a v115 fumen")?;
+    let mut values = Values::parse(data.trim())?;
+    let mut pages = Vec::new();
+    let mut field: Field = [0; FIELD_BLOCKS];
+    let mut repeat = 0;
+    let mut comment = String::new();
+    while !values.is_empty() {
+        if repeat > 0 {
+            repeat -= 1;
+        } else {
+            let mut index = 0;
+            while index < FIELD_BLOCKS {
+                let run = values.poll(2)?;
+                let diff = (run / FIELD_BLOCKS as u32) as i32 - 8;
+                let count = (run % FIELD_BLOCKS as u32 + 1) as usize;
+                let cells = field
+                    .get_mut(index..index + count)
+                    .ok_or("fumen field runs past the end")?;
+                for cell in cells {
+                    *cell += diff;
+                }
+                index += count;
+                if run == UNCHANGED_FIELD {
+                    repeat = values.poll(1)?;
+                }
+            }
+            if field.iter().any(|id| !(0..=8).contains(id)) {
+                return Err("fumen field has unknown blocks".to_string());
+            }
+        }
+
+        let action = values.poll(3)?;
+        let piece_id = (action % 8) as i32;
+        let rotation = ROTATIONS[(action / 8 % 4) as usize];
+        let position = (action / 32 % FIELD_BLOCKS as u32) as i32;
+        let flags = action / (32 * FIELD_BLOCKS as u32);
+        let lock = flags & NO_LOCK == 0;
+        if flags & COMMENT != 0 {
+            let length = values.poll(2)? as usize;
+            let mut escaped = String::new();
+            for _ in 0..length.div_ceil(4) {
+                let mut packed = values.poll(5)?;
+                for _ in 0..4 {
+                    escaped.push(char::from_u32(packed % COMMENT_CHARS + 32).unwrap_or(' '));
+                    packed /= COMMENT_CHARS;
+                }
+            }
+            escaped.truncate(length);
+            comment = unescape(&escaped);
+        }
+
+        let cells = match piece_id {
+            0 => None,
+            _ => Some(fumen_cells(piece_id, rotation, position)?),
+        };
+        let mut page = FumenPage {
+            board: GameBoard::new(FIELD_WIDTH, FIELD_HEIGHT),
+            piece: None,
+            lock,
+            comment: comment.clone(),
+        };
+        for row in 0..FIELD_HEIGHT {
+            for x in 0..FIELD_WIDTH {
+                page.board.set_cell(
+                    x,
+                    row,
+                    FROM_FUMEN[field[(x + row * FIELD_WIDTH) as usize] as usize],
+                );
+            }
+        }
+        if let Some(cells) = &cells {
+            let piece_type = piece_type(FROM_FUMEN[piece_id as usize]);
+            page.piece = Some(piece_on_cells(piece_type, rotation, cells)?);
+        }
+        pages.push(page);
+
+        if lock {
+            for (x, row) in cells.iter().flatten() {
+                field[(x + row * FIELD_WIDTH) as usize] = piece_id;
+            }
+            clear_lines(&mut field);
+            if flags & RISE != 0 {
+                field.copy_within(FIELD_WIDTH as usize.., 0);
+                field[FIELD_BLOCKS - FIELD_WIDTH as usize..].fill(0);
+            }
+            if flags & MIRROR != 0 {
+                for row in field[..FIELD_BLOCKS - FIELD_WIDTH as usize].chunks_mut(10) {
+                    row.reverse();
+                }
+            }
+        }
+    }
+    if pages.is_empty() {
+        return Err("fumen has no pages".to_string());
+    }
+    Ok(pages)
+}
+
+// Writes pages as a fumen string. Their boards have to be FIELD_WIDTH wide and
+// FIELD_HEIGHT high, like the ones from_board makes.
+pub fn encode(pages: &[FumenPage]) -> Result<String, String> {
+    let mut values = Vec::new();
+    let mut prev: Field = [0; FIELD_BLOCKS];
+    let mut repeat_index: Option<usize> = None;
+    let mut prev_comment = "";
+    for (page_index, page) in pages.iter().enumerate() {
+        let board = &page.board;
+        if (board.width, board.height) != (FIELD_WIDTH, FIELD_HEIGHT) {
+            return Err(format!("fumen pages are {}x{}", FIELD_WIDTH, FIELD_HEIGHT));
+        }
+        let mut field = prev;
+        for row in 0..FIELD_HEIGHT {
+            for x in 0..FIELD_WIDTH {
+                field[(x + row * FIELD_WIDTH) as usize] = TO_FUMEN[board.cell(x, row) as usize];
+            }
+        }
+
+        if field == prev {
+            match repeat_index {
+                Some(index) if values[index] < 63 => values[index] += 1,
+                _ => {
+                    push(&mut values, UNCHANGED_FIELD, 2);
+                    values.push(0);
+                    repeat_index = Some(values.len() - 1);
+                }
+            }
+        } else {
+            repeat_index = None;
+            let mut run_diff = field[0] - prev[0] + 8;
+            let mut count = 0;
+            for (cell, prev_cell) in field.iter().zip(prev.iter()) {
+                let diff = cell - prev_cell + 8;
+                if diff != run_diff {
+                    push(
+                        &mut values,
+                        run_diff as u32 * FIELD_BLOCKS as u32 + count - 1,
+                        2,
+                    );
+                    run_diff = diff;
+                    count = 0;
+                }
+                count += 1;
+            }
+            push(
+                &mut values,
+                run_diff as u32 * FIELD_BLOCKS as u32 + count - 1,
+                2,
+            );
+        }
+
+        let (piece_action, cells) = match &page.piece {
+            Some(piece) => encode_piece(piece)?,
+            None => (0, Vec::new()),
+        };
+        let has_comment = page.comment != prev_comment;
+        let mut flags = if page_index == 0 { COLORIZE } else { 0 };
+        if has_comment {
+            flags |= COMMENT;
+        }
+        if !page.lock {
+            flags |= NO_LOCK;
+        }
+        push(
+            &mut values,
+            flags * FIELD_BLOCKS as u32 * 32 + piece_action,
+            3,
+        );
+        if has_comment {
+            let escaped: Vec<u32> = escape(&page.comment)
+                .bytes()
+                .take(4095)
+                .map(|b| (b - 32) as u32)
+                .collect();
+            push(&mut values, escaped.len() as u32, 2);
+            for chunk in escaped.chunks(4) {
+                let packed = chunk
+                    .iter()
+                    .rev()
+                    .fold(0, |packed, c| packed * COMMENT_CHARS + c);
+                push(&mut values, packed, 5);
+            }
+            prev_comment = &page.comment;
+        }
+
+        if page.lock {
+            for (x, row) in cells {
+                field[(x + row * FIELD_WIDTH) as usize] = (piece_action % 8) as i32;
+            }
+            clear_lines(&mut field);
+        }
+        prev = field;
+    }
+
+    // Fumen breaks the data up with a ? after the first 42 characters and every 47 after.
+    let data: Vec<u8> = values.iter().map(|v| ENCODE_TABLE[*v as usize]).collect();
+    let (head, tail) = data.split_at(data.len().min(42));
+    let mut chunks = vec![head];
+    chunks.extend(tail.chunks(47));
+    let chunks: Vec<&str> = chunks
+        .iter()
+        .map(|chunk| std::str::from_utf8(chunk).unwrap())
+        .collect();
+    Ok(format!("{}{}", PREFIXES[0], chunks.join("?")))
+}
+
+// Stored rotation numbers are reverse, right, spawn, left. The board counts clockwise
+// from spawn.
+const ROTATIONS: [u8; 4] = [2, 1, 0, 3];
+
+fn piece_type(id: i32) -> TetrisPieceType {
+    [
+        TetrisPieceType::I,
+        TetrisPieceType::O,
+        TetrisPieceType::T,
+        TetrisPieceType::S,
+        TetrisPieceType::Z,
+        TetrisPieceType::J,
+        TetrisPieceType::L,
+    ][id as usize - 1]
+}
+
+// Offsets of a piece's cells in a rotation, fumen ids, y up.
+fn rotated_blocks(piece_id: i32, rotation: u8) -> [(i32, i32); 4] {
+    FUMEN_BLOCKS[FROM_FUMEN[piece_id as usize] as usize - 1].map(|(x, y)| match rotation {
+        1 => (y, -x),
+        2 => (-x, -y),
+        3 => (-y, x),
+        _ => (x, y),
+    })
+}
+
+// Fumen stores some rotations of O, I, S and Z one cell off from the point their cells
+// are placed around.
+fn position_shift(piece_id: i32, rotation: u8) -> (i32, i32) {
+    match (piece_type(FROM_FUMEN[piece_id as usize]), rotation) {
+        (TetrisPieceType::O, 3) => (1, -1),
+        (TetrisPieceType::O, 2) | (TetrisPieceType::I, 2) | (TetrisPieceType::Z, 3) => (1, 0),
+        (TetrisPieceType::O, 0)
+        | (TetrisPieceType::I, 3)
+        | (TetrisPieceType::S, 0)
+        | (TetrisPieceType::Z, 0) => (0, -1),
+        (TetrisPieceType::S, 1) => (-1, 0),
+        _ => (0, 0),
+    }
+}
+
+// Cells a piece covers as (x, row) with row 0 at the top of the field.
+fn fumen_cells(piece_id: i32, rotation: u8, position: i32) -> Result<Vec<(i32, i32)>, String> {
+    let (dx, dy) = position_shift(piece_id, rotation);
+    let x = position % FIELD_WIDTH + dx;
+    let y = FIELD_HEIGHT - 1 - position / FIELD_WIDTH + dy;
+    let cells: Vec<(i32, i32)> = rotated_blocks(piece_id, rotation)
+        .iter()
+        .map(|(bx, by)| (x + bx, FIELD_HEIGHT - 1 - (y + by)))
+        .collect();
+    if cells
+        .iter()
+        .any(|(x, row)| !(0..FIELD_WIDTH).contains(x) || !(0..FIELD_HEIGHT).contains(row))
+    {
+        return Err("fumen piece is outside of the field".to_string());
+    }
+    Ok(cells)
+}
+
+// The piece in the given rotation whose cells are exactly these.
+fn piece_on_cells(
+    piece_type: TetrisPieceType,
+    rotation: u8,
+    cells: &[(i32, i32)],
+) -> Result<TetrisPiece, String> {
+    let mut piece = TetrisPiece::new(piece_type);
+    piece.rotation = rotation;
+    (piece.x, piece.y) = (0, 0);
+    let min = |cells: &mut dyn Iterator<Item = (i32, i32)>| {
+        cells.fold((i32::MAX, i32::MAX), |(mx, my), (x, y)| {
+            (mx.min(x), my.min(y))
+        })
+    };
+    let (cell_x, cell_y) = min(&mut cells.iter().copied());
+    let (box_x, box_y) = min(&mut piece.cells());
+    (piece.x, piece.y) = (cell_x - box_x, cell_y - box_y);
+    let mut placed: Vec<(i32, i32)> = piece
+        .cells()
+        .map(|(x, y)| (piece.x + x, piece.y + y))
+        .collect();
+    let mut expected = cells.to_vec();
+    placed.sort();
+    expected.sort();
+    if placed != expected {
+        return Err("fumen piece doesn't match the board's pieces".to_string());
+    }
+    Ok(piece)
+}
+
+// The piece's part of a page's action, its position, rotation and fumen id, and the cells
+// it covers.
+fn encode_piece(piece: &TetrisPiece) -> Result<(u32, Vec<(i32, i32)>), String> {
+    let piece_id = TO_FUMEN[piece.piece_type.id() as usize];
+    let rotation = piece.rotation;
+    let mut cells: Vec<(i32, i32)> = piece
+        .cells()
+        .map(|(x, y)| (piece.x + x, piece.y + y))
+        .collect();
+    cells.sort();
+    // The first cell of the piece is the first of fumen's blocks, which gives the point the
+    // blocks are placed around.
+    let (x, row) = cells[0];
+    let (block_x, block_y) = rotated_blocks(piece_id, rotation)
+        .iter()
+        .map(|(x, y)| (*x, -*y))
+        .min()
+        .unwrap();
+    let (dx, dy) = position_shift(piece_id, rotation);
+    let stored_x = x - block_x - dx;
+    let stored_y = FIELD_HEIGHT - 1 - (row - block_y) - dy;
+    let outside = || "the piece is outside of the fumen field".to_string();
+    if !(0..FIELD_WIDTH).contains(&stored_x) || !(0..FIELD_HEIGHT).contains(&stored_y) {
+        return Err(outside());
+    }
+    let position = (FIELD_HEIGHT - 1 - stored_y) * FIELD_WIDTH + stored_x;
+    let mut stored_cells = fumen_cells(piece_id, rotation, position).map_err(|_| outside())?;
+    stored_cells.sort();
+    if stored_cells != cells {
+        return Err(outside());
+    }
+    let stored_rotation = ROTATIONS.iter().position(|r| *r == rotation).unwrap() as u32;
+    Ok((
+        (position as u32 * 4 + stored_rotation) * 8 + piece_id as u32,
+        cells,
+    ))
+}
+
+// Removes the full rows of the field above the garbage row.
+fn clear_lines(field: &mut Field) {
+    let width = FIELD_WIDTH as usize;
+    for row in 0..FIELD_HEIGHT as usize {
+        if field[row * width..(row + 1) * width]
+            .iter()
+            .all(|id| *id != 0)
+        {
+            field.copy_within(0..row * width, width);
+            field[..width].fill(0);
+        }
+    }
+}
+
+// Appends a number as `digits` base 64 digits, least significant first.
+fn push(values: &mut Vec<u32>, mut value: u32, digits: usize) {
+    for _ in 0..digits {
+        values.push(value % 64);
+        value /= 64;
+    }
+}
+
+// The base 64 digits of fumen data, read front to back.
+struct Values {
+    digits: Vec<u32>,
+    next: usize,
+}
+
+impl Values {
+    fn parse(data: &str) -> Result<Values, String> {
+        let digits = data
+            .bytes()
+            .filter(|b| *b != b'?')
+            .map(|b| ENCODE_TABLE.iter().position(|c| *c == b).map(|v| v as u32))
+            .collect::<Option<Vec<u32>>>()
+            .ok_or("fumen data has characters outside its alphabet")?;
+        Ok(Values { digits, next: 0 })
+    }
+
+    fn is_empty(&self) -> bool {
+        self.next >= self.digits.len()
+    }
+
+    fn poll(&mut self, digits: usize) -> Result<u32, String> {
+        let values = self
+            .digits
+            .get(self.next..self.next + digits)
+            .ok_or("fumen data ends early")?;
+        self.next += digits;
+        Ok(values
+            .iter()
+            .rev()
+            .fold(0, |value, digit| value * 64 + digit))
+    }
+}
+
+// Comments are stored the way JavaScript's escape() writes them.
+fn escape(text: &str) -> String {
+    let mut escaped = String::new();
+    for unit in text.encode_utf16() {
+        match char::from_u32(unit as u32) {
+            Some(c) if c.is_ascii_alphanumeric() || "@*_+-./".contains(c) => escaped.push(c),
+            _ if unit < 256 => escaped.push_str(&format!("%{:02X}", unit)),
+            _ => escaped.push_str(&format!("%u{:04X}", unit)),
+        }
+    }
+    escaped
+}
+
+fn unescape(text: &str) -> String {
+    let mut units = Vec::new();
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        let code = match rest.strip_prefix("%u") {
+            Some(hex) => hex.get(..4).map(|hex| (hex, 6)),
+            None if c == '%' => rest.get(1..3).map(|hex| (hex, 3)),
+            None => None,
+        };
+        match code.and_then(|(hex, len)| Some((u16::from_str_radix(hex, 16).ok()?, len))) {
+            Some((unit, len)) => {
+                units.push(unit);
+                rest = &rest[len..];
+            }
+            None => {
+                units.extend(c.encode_utf16(&mut [0; 2]).iter());
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+    }
+    String::from_utf16_lossy(&units)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game_board::GARBAGE_ID;
+
+    fn blocks(page: &FumenPage) -> Vec<(i32, i32, i32)> {
+        let board = &page.board;
+        (0..board.height)
+            .flat_map(|y| (0..board.width).map(move |x| (x, y)))
+            .filter(|(x, y)| board.cell(*x, *y) != 0)
+            .map(|(x, y)| (x, y, board.cell(x, y)))
+            .collect()
+    }
+
+    #[test]
+    fn test_decode() {
+        let pages = decode("https://fumen.zui.jp/?v115@vhAAgH").unwrap();
+        assert_eq!(pages.len(), 1);
+        assert!(pages[0].board.is_empty());
+        assert_eq!(pages[0].piece, None);
+        assert!(pages[0].lock);
+        assert_eq!(encode(&pages).unwrap(), "v115@vhAAgH");
+        // Four rows with six gray blocks on the left.
+        let text = "v115@9gF8DeF8DeF8DeF8NeAgH";
+        let pages = decode(text).unwrap();
+        assert_eq!(blocks(&pages[0]).len(), 24);
+        assert_eq!(pages[0].board.cell(5, 19), GARBAGE_ID);
+        assert_eq!(pages[0].board.cell(6, 22), 0);
+        assert_eq!(pages[0].board.cell(0, 18), 0);
+        assert_eq!(encode(&pages).unwrap(), text);
+        assert!(decode("v110@7eEfj").is_err());
+        assert!(decode("v115@").is_err());
+        assert!(decode("v115@vh").is_err());
+    }
+
+    // Cells of a page's piece, sorted.
+    fn piece_cells(page: &FumenPage) -> Vec<(i32, i32)> {
+        let piece = page.piece.unwrap();
+        let mut cells: Vec<(i32, i32)> = piece
+            .cells()
+            .map(|(x, y)| (piece.x + x, piece.y + y))
+            .collect();
+        cells.sort();
+        cells
+    }
+
+    #[test]
+    fn test_decode_editor_pages() {
+        // An empty field with the comment "Hi!", escaped to "Hi%21".
+        let pages = decode("v115@vhAAgWFAI+88ARAAAA").unwrap();
+        assert_eq!(pages.len(), 1);
+        assert_eq!(pages[0].comment, "Hi!");
+        assert!(pages[0].lock);
+        assert_eq!(encode(&pages).unwrap(), "v115@vhAAgWFAI+88ARAAAA");
+
+        // A T in spawn orientation on the bottom row that isn't locked.
+        let pages = decode("v115@vhAVQn").unwrap();
+        assert_eq!(pages.len(), 1);
+        assert!(!pages[0].lock);
+        assert_eq!(pages[0].piece.unwrap().piece_type, TetrisPieceType::T);
+        assert_eq!(piece_cells(&pages[0]), [(3, 22), (4, 21), (4, 22), (5, 22)]);
+        assert_eq!(encode(&pages).unwrap(), "v115@vhAVQn");
+
+        // I, O, S and Z at the same spot in spawn, right, reverse and left orientation,
+        // where the editor draws the reverse and left ones over the spawn and right ones.
+        let text = "v115@vhPR3mJXfBXfZXfTXfLXfDXfbXfXXfPXfHXffXfUXf?MXfEXfcXf";
+        let pages = decode(text).unwrap();
+        let expected = [
+            [
+                [(3, 17), (4, 17), (5, 17), (6, 17)],
+                [(4, 16), (4, 17), (4, 18), (4, 19)],
+            ],
+            [
+                [(4, 17), (4, 18), (5, 17), (5, 18)],
+                [(4, 17), (4, 18), (5, 17), (5, 18)],
+            ],
+            [
+                [(3, 18), (4, 17), (4, 18), (5, 17)],
+                [(3, 16), (3, 17), (4, 17), (4, 18)],
+            ],
+            [
+                [(3, 17), (4, 17), (4, 18), (5, 18)],
+                [(4, 17), (4, 18), (5, 16), (5, 17)],
+            ],
+        ];
+        assert_eq!(pages.len(), 16);
+        for (i, page) in pages.iter().enumerate() {
+            assert_eq!(page.piece.unwrap().rotation, i as u8 % 4);
+            assert_eq!(piece_cells(page), expected[i / 4][i % 2]);
+            assert!(!page.lock && page.board.is_empty());
+        }
+        assert_eq!(encode(&pages).unwrap(), text);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut board = GameBoard::with_hidden_rows(10, 20, 20);
+        let bottom = board.height - 1;
+        for x in 0..9 {
+            board.set_cell(x, bottom, GARBAGE_ID);
+            board.set_cell(x, bottom - 1, TetrisPieceType::L.id());
+        }
+        let mut first = FumenPage::from_board(&board, None).unwrap();
+        first.comment = "Tetris ready? 100%".to_string();
+
+        // Every piece in every rotation, standing on the stack in the middle of the field.
+        let mut pages = vec![first.clone()];
+        for id in 1..=7 {
+            for rotation in 0..4 {
+                let mut piece = TetrisPiece::new(piece_type(id));
+                piece.rotation = rotation;
+                piece.x = 3;
+                piece.y = 14;
+                pages.push(FumenPage {
+                    board: first.board.clone(),
+                    piece: Some(piece),
+                    lock: false,
+                    comment: format!("{:?} {}", piece.piece_type, rotation),
+                });
+            }
+        }
+        // An I dropped in the well clears both rows, half of it is left on the next page.
+        let mut well = TetrisPiece::new(TetrisPieceType::I);
+        well.rotation = 1;
+        well.x = 7;
+        well.y = FIELD_HEIGHT - 5;
+        let locked = FumenPage {
+            piece: Some(well),
+            lock: true,
+            ..first.clone()
+        };
+        pages.push(locked);
+        let mut after = FumenPage::from_board(&GameBoard::new(10, 23), None).unwrap();
+        after.board.set_cell(9, 21, TetrisPieceType::I.id());
+        after.board.set_cell(9, 22, TetrisPieceType::I.id());
+        pages.extend([after.clone(), after]);
+
+        let encoded = encode(&pages).unwrap();
+        assert!(encoded.contains('?'));
+        let decoded = decode(&encoded).unwrap();
+        assert_eq!(decoded.len(), pages.len());
+        for (page, expected) in decoded.iter().zip(&pages) {
+            assert_eq!(blocks(page), blocks(expected));
+            assert_eq!(page.piece, expected.piece);
+            assert_eq!(page.comment, expected.comment);
+            assert_eq!(page.lock, expected.lock);
+        }
+
+        let mut loaded = GameBoard::with_hidden_rows(10, 20, 20);
+        decoded[0].fill_board(&mut loaded).unwrap();
+        assert_eq!(loaded.grid(), board.grid());
+    }
+
+    #[test]
+    fn test_fumen_url() {
+        let mut board = GameBoard::with_hidden_rows(10, 20, 20);
+        let mut piece = TetrisPiece::new(TetrisPieceType::T);
+        piece.spawn(&board);
+        let url = fumen_url(&board, Some(&piece)).unwrap();
+        assert!(url.starts_with("https://fumen.zui.jp/?v115@"));
+        let pages = decode(&url).unwrap();
+        assert_eq!(pages[0].piece.unwrap().piece_type, TetrisPieceType::T);
+
+        board.set_cell(0, 2, GARBAGE_ID);
+        assert!(fumen_url(&board, None).is_err());
+        assert!(fumen_url(&GameBoard::new(8, 20), None).is_err());
+    }
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape("a b%ü€"), "a%20b%25%FC%u20AC");
+        assert_eq!(unescape("a%20b%25%FC%u20AC"), "a b%ü€");
+    }
+}
@@ -1,70 +1,600 @@
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
 use crate::TetrisPiece;
 
-#[derive(Debug)]
+// Rows are stored as bitmasks, a piece hanging off the right edge needs 5 spare bits.
+pub const MAX_WIDTH: i32 = 27;
+
+// Block id of garbage, one past the ids of the seven pieces.
+pub const GARBAGE_ID: i32 = 8;
+
+// Characters of the text form of a board, indexed by block id: `.` for an empty cell,
+// the letter of the piece a block came from and `X` for garbage.
+const BLOCK_CHARS: [char; 9] = ['.', 'I', 'O', 'T', 'S', 'Z', 'J', 'L', 'X'];
+
+pub fn block_char(id: i32) -> char {
+    usize::try_from(id)
+        .ok()
+        .and_then(|id| BLOCK_CHARS.get(id))
+        .copied()
+        .unwrap_or('?')
+}
+
+pub fn block_id(c: char) -> Option<i32> {
+    BLOCK_CHARS.iter().position(|&b| b == c).map(|id| id as i32)
+}
+
+// How the blocks above a cleared row move once it is removed.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ClearGravity {
+    Naive,  // everything above a cleared row drops by one row
+    Sticky, // connected groups of blocks fall until they land, which can clear more rows
+    None,   // cleared rows are emptied and nothing falls
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ClearResult {
+    pub rows: Vec<i32>,              // cleared rows, indexed as they were when cleared
+    pub cells: Vec<(i32, i32, i32)>, // removed blocks as (x, y, block id)
+    pub cascades: u32,               // extra clears caused by sticky gravity
+}
+
+impl ClearResult {
+    // Cleared rows that still had garbage in them.
+    pub fn garbage_rows(&self) -> u32 {
+        if self.rows.is_empty() {
+            return 0;
+        }
+        let width = self.cells.len() / self.rows.len();
+        self.cells
+            .chunks(width)
+            .filter(|row| row.iter().any(|(_, _, id)| *id == GARBAGE_ID))
+            .count() as u32
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GameBoard {
-    pub grid: Vec<i32>,
+    rows: Vec<u32>,       // occupied cells of every row, bit x set for column x
+    grid: Vec<i32>,       // block ids for rendering, indexed as x + y * width
+    lock_ticks: Vec<u32>, // game tick at which each block was locked, indexed like grid
+    full_row: u32,
+    garbage_hole: Option<i32>, // hole of the last garbage row pushed in
     pub width: i32,
     pub height: i32,
+    pub hidden_rows: i32, // rows at the top of the grid that are above the visible field
+    pub clear_gravity: ClearGravity,
 }
 
 impl GameBoard {
     pub fn new(width: i32, height: i32) -> GameBoard {
+        assert!(
+            width > 0 && width <= MAX_WIDTH,
+            "invalid board width {}",
+            width
+        );
         GameBoard {
+            rows: vec![0; height as usize],
             grid: vec![0; (width * height) as usize],
+            lock_ticks: vec![0; (width * height) as usize],
+            full_row: (1 << width) - 1,
+            garbage_hole: None,
             width,
             height,
+            hidden_rows: 0,
+            clear_gravity: ClearGravity::Naive,
         }
     }
 
+    // A board with a vanish zone of hidden_rows rows stacked on top of the visible field.
+    pub fn with_hidden_rows(width: i32, visible_height: i32, hidden_rows: i32) -> GameBoard {
+        let mut board = GameBoard::new(width, visible_height + hidden_rows);
+        board.hidden_rows = hidden_rows;
+        board
+    }
+
+    pub fn visible_height(&self) -> i32 {
+        self.height - self.hidden_rows
+    }
+
+    // Position of a piece's 5x5 box so that it spawns centered in the two rows
+    // directly above the visible field.
+    pub fn spawn_position(&self) -> (i32, i32) {
+        ((self.width - 4) / 2 - 1, (self.hidden_rows - 3).max(-1))
+    }
+
+    #[allow(dead_code)]
+    pub fn from_grid(width: i32, height: i32, grid: Vec<i32>) -> GameBoard {
+        assert_eq!(grid.len(), (width * height) as usize);
+        let mut board = GameBoard::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                board.set_cell(x, y, grid[(x + y * width) as usize]);
+            }
+        }
+        board
+    }
+
+    #[allow(dead_code)]
+    pub fn grid(&self) -> &[i32] {
+        &self.grid
+    }
+
+    pub fn clear(&mut self) {
+        self.rows.fill(0);
+        self.grid.fill(0);
+        self.lock_ticks.fill(0);
+        self.garbage_hole = None;
+    }
+
+    // FNV-1a hash of the size and every cell, the same on every platform and build, so
+    // boards from different machines can be compared.
+    pub fn grid_hash(&self) -> u64 {
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+        for value in [self.width, self.height].iter().chain(&self.grid) {
+            for byte in value.to_le_bytes() {
+                hash = (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3);
+            }
+        }
+        hash
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.iter().all(|row| *row == 0)
+    }
+
+    // Rows from the floor up to the highest block.
+    pub fn stack_height(&self) -> i32 {
+        self.rows
+            .iter()
+            .position(|row| *row != 0)
+            .map_or(0, |y| self.height - y as i32)
+    }
+
+    pub fn cell(&self, x: i32, y: i32) -> i32 {
+        self.grid[(x + y * self.width) as usize]
+    }
+
+    pub fn set_cell(&mut self, x: i32, y: i32, id: i32) {
+        self.grid[(x + y * self.width) as usize] = id;
+        if id != 0 {
+            self.rows[y as usize] |= 1 << x;
+        } else {
+            self.rows[y as usize] &= !(1 << x);
+        }
+    }
+
+    // Tick at which the block in a cell was locked, 0 for garbage and blocks set directly.
+    pub fn lock_tick(&self, x: i32, y: i32) -> u32 {
+        self.lock_ticks[(x + y * self.width) as usize]
+    }
+
     pub fn test_placement(&self, piece: &TetrisPiece) -> bool {
-        for x in 0..5 {
-            for y in 0..5 {
-                if piece.grid[x + y * 5] != 0 {
-                    if (piece.x + x as i32) >= self.width || (piece.y + y as i32) >= self.height {
-                        return false;
-                    }
-                    if (piece.x + x as i32) < 0 || (piece.y + y as i32) < 0 {
-                        return false;
-                    }
-                    if self.grid
-                        [((piece.x + x as i32) + (piece.y + y as i32) * self.width) as usize]
-                        != 0
-                    {
-                        return false;
-                    }
+        for y in 0..5 {
+            let piece_row = piece.row_mask(y);
+            if piece_row == 0 {
+                continue;
+            }
+            let board_y = piece.y + y;
+            // Past either side every cell of the 5 wide row is off the board, and shifting
+            // by that much would overflow.
+            if board_y < 0 || board_y >= self.height || piece.x > self.width || piece.x <= -5 {
+                return false;
+            }
+            let shifted = if piece.x >= 0 {
+                piece_row << piece.x
+            } else {
+                if piece_row & ((1 << -piece.x) - 1) != 0 {
+                    return false;
                 }
+                piece_row >> -piece.x
+            };
+            if shifted & !self.full_row != 0 || shifted & self.rows[board_y as usize] != 0 {
+                return false;
             }
         }
         true
     }
 
     pub fn place_piece(&mut self, piece: &TetrisPiece) -> &mut GameBoard {
-        for x in 0..5 {
-            for y in 0..5 {
-                if piece.grid[x + y * 5] != 0 {
-                    self.grid
-                        [((piece.x + x as i32) + (piece.y + y as i32) * self.width) as usize] =
-                        piece.grid[x + y * 5];
+        for (x, y) in piece.cells() {
+            self.set_cell(piece.x + x, piece.y + y, piece.piece_type.id());
+        }
+        self
+    }
+
+    // Places a piece and remembers when it was locked, e.g. to fade it out later.
+    pub fn lock_piece(&mut self, piece: &TetrisPiece, tick: u32) {
+        self.place_piece(piece);
+        for (x, y) in piece.cells() {
+            self.lock_ticks[(piece.x + x + (piece.y + y) * self.width) as usize] = tick;
+        }
+    }
+
+    // Pushes rows of garbage in from the bottom, each with a single hole. A row keeps the
+    // hole of the row before it, except that with a chance of hole_change percent the hole
+    // moves to another column. Returns whether blocks were pushed out of the top of the board.
+    pub fn insert_garbage_rows(
+        &mut self,
+        count: i32,
+        hole_change: u32,
+        rng: &mut impl Rng,
+    ) -> bool {
+        let count = count.clamp(0, self.height) as usize;
+        let width = self.width as usize;
+        let overflow = self.rows[..count].iter().any(|row| *row != 0);
+        self.rows.copy_within(count.., 0);
+        self.grid.copy_within(count * width.., 0);
+        self.lock_ticks.copy_within(count * width.., 0);
+        let garbage_start = (self.height as usize - count) * width;
+        self.lock_ticks[garbage_start..].fill(0);
+        for y in self.height - count as i32..self.height {
+            let hole = match self.garbage_hole {
+                Some(hole) if !rng.gen_ratio(hole_change.min(100), 100) => hole,
+                Some(hole) => {
+                    let other = rng.gen_range(0..self.width - 1);
+                    if other >= hole {
+                        other + 1
+                    } else {
+                        other
+                    }
                 }
+                None => rng.gen_range(0..self.width),
+            };
+            self.garbage_hole = Some(hole);
+            for x in 0..self.width {
+                self.set_cell(x, y, if x == hole { 0 } else { GARBAGE_ID });
             }
         }
-        self
+        overflow
     }
 
     pub fn full_lines(&self) -> Vec<i32> {
-        let mut full_lines: Vec<i32> = Vec::new();
-        for y in 0..self.height {
-            let mut full_line: bool = true;
-            for x in 0..self.width {
-                if self.grid[(x + y * self.width) as usize] == 0 {
-                    full_line = false;
-                    break;
+        (0..self.height)
+            .filter(|y| self.rows[*y as usize].count_ones() == self.width as u32)
+            .collect()
+    }
+
+    // Removes all full rows and lets the blocks above them fall according to clear_gravity.
+    pub fn clear_lines(&mut self) -> ClearResult {
+        let mut result = ClearResult::default();
+        let mut full_lines = self.full_lines();
+        while !full_lines.is_empty() {
+            if !result.rows.is_empty() {
+                result.cascades += 1;
+            }
+            for line in full_lines {
+                for x in 0..self.width {
+                    result.cells.push((x, line, self.cell(x, line)));
+                }
+                result.rows.push(line);
+                match self.clear_gravity {
+                    ClearGravity::Naive => self.clear_line(line),
+                    ClearGravity::Sticky | ClearGravity::None => self.empty_line(line),
                 }
             }
-            if full_line {
-                full_lines.push(y);
+            if self.clear_gravity != ClearGravity::Sticky {
+                break;
+            }
+            self.drop_sticky_groups();
+            full_lines = self.full_lines();
+        }
+        result
+    }
+
+    // Removes row y and shifts everything above it down by one.
+    fn clear_line(&mut self, y: i32) {
+        let width = self.width as usize;
+        let y = y as usize;
+        self.rows.copy_within(0..y, 1);
+        self.rows[0] = 0;
+        self.grid.copy_within(0..y * width, width);
+        self.grid[0..width].fill(0);
+        self.lock_ticks.copy_within(0..y * width, width);
+        self.lock_ticks[0..width].fill(0);
+    }
+
+    fn empty_line(&mut self, y: i32) {
+        let width = self.width as usize;
+        let y = y as usize;
+        self.rows[y] = 0;
+        self.grid[y * width..(y + 1) * width].fill(0);
+        self.lock_ticks[y * width..(y + 1) * width].fill(0);
+    }
+
+    // Splits the stack into groups of orthogonally connected blocks and drops each group
+    // as a rigid body, bottom groups first, until nothing can fall any further.
+    fn drop_sticky_groups(&mut self) {
+        let mut labels: Vec<usize> = vec![usize::MAX; self.grid.len()];
+        let mut groups: Vec<Vec<(i32, i32)>> = Vec::new();
+        for start in 0..self.grid.len() {
+            if self.grid[start] == 0 || labels[start] != usize::MAX {
+                continue;
+            }
+            let mut group: Vec<(i32, i32)> = Vec::new();
+            let mut stack = vec![start];
+            labels[start] = groups.len();
+            while let Some(i) = stack.pop() {
+                let (x, y) = (i as i32 % self.width, i as i32 / self.width);
+                group.push((x, y));
+                for (nx, ny) in [(x - 1, y), (x + 1, y), (x, y - 1), (x, y + 1)] {
+                    if nx < 0 || ny < 0 || nx >= self.width || ny >= self.height {
+                        continue;
+                    }
+                    let n = (nx + ny * self.width) as usize;
+                    if self.grid[n] != 0 && labels[n] == usize::MAX {
+                        labels[n] = groups.len();
+                        stack.push(n);
+                    }
+                }
+            }
+            groups.push(group);
+        }
+        groups.sort_by_key(|group| -group.iter().map(|(_, y)| *y).max().unwrap_or(0));
+
+        let mut moved = true;
+        while moved {
+            moved = false;
+            for (label, group) in groups.iter_mut().enumerate() {
+                let can_fall = group.iter().all(|(x, y)| {
+                    let below = (x + (y + 1) * self.width) as usize;
+                    y + 1 < self.height && (self.grid[below] == 0 || labels[below] == label)
+                });
+                if !can_fall {
+                    continue;
+                }
+                let blocks: Vec<(i32, u32)> = group
+                    .iter()
+                    .map(|(x, y)| (self.cell(*x, *y), self.lock_tick(*x, *y)))
+                    .collect();
+                for (x, y) in group.iter() {
+                    self.set_cell(*x, *y, 0);
+                    labels[(x + y * self.width) as usize] = usize::MAX;
+                }
+                for ((x, y), (id, tick)) in group.iter_mut().zip(blocks) {
+                    *y += 1;
+                    self.set_cell(*x, *y, id);
+                    labels[(*x + *y * self.width) as usize] = label;
+                    self.lock_ticks[(*x + *y * self.width) as usize] = tick;
+                }
+                moved = true;
+            }
+        }
+    }
+}
+
+// A board is written a row per line from the top, e.g.
+//
+//     ..........
+//     ....T.....
+//     XXXTTT.XXX
+impl fmt::Display for GameBoard {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for row in self.grid.chunks(self.width as usize) {
+            let line: String = row.iter().map(|id| block_char(*id)).collect();
+            writeln!(f, "{}", line)?;
+        }
+        Ok(())
+    }
+}
+
+// Reads the text form back, ignoring indentation and blank lines. The board is as wide
+// as the rows and as high as there are rows.
+impl FromStr for GameBoard {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<GameBoard, String> {
+        let rows: Vec<&str> = text
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .collect();
+        let width = rows.first().map_or(0, |row| row.chars().count()) as i32;
+        if width == 0 || width > MAX_WIDTH {
+            return Err(format!("invalid board width {}", width));
+        }
+        let mut board = GameBoard::new(width, rows.len() as i32);
+        for (y, row) in rows.iter().enumerate() {
+            if row.chars().count() as i32 != width {
+                return Err(format!("board row {} is not {} cells wide", y + 1, width));
+            }
+            for (x, c) in row.chars().enumerate() {
+                let id = block_id(c).ok_or(format!("bad board cell '{}'", c))?;
+                board.set_cell(x as i32, y as i32, id);
+            }
+        }
+        Ok(board)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::GameRng;
+    use crate::tetris_piece::TetrisPieceType;
+
+    #[test]
+    fn test_placement_bounds() {
+        let mut b = GameBoard::new(10, 20);
+        let mut t = TetrisPiece::new(TetrisPieceType::I);
+        t.x = -1;
+        assert!(b.test_placement(&t));
+        t.x = -2;
+        assert!(!b.test_placement(&t));
+        t.x = 5;
+        assert!(b.test_placement(&t));
+        t.x = 6;
+        assert!(!b.test_placement(&t));
+        for x in [-5, -40, 40] {
+            t.x = x;
+            assert!(!b.test_placement(&t));
+        }
+        t.x = 2;
+        t.y = 17;
+        assert!(b.test_placement(&t));
+        t.y = 18;
+        assert!(!b.test_placement(&t));
+        b.set_cell(4, 19, 1);
+        t.y = 17;
+        assert!(!b.test_placement(&t));
+    }
+
+    #[test]
+    fn test_spawn_position() {
+        let b = GameBoard::with_hidden_rows(10, 20, 20);
+        assert_eq!(b.height, 40);
+        assert_eq!(b.visible_height(), 20);
+        assert_eq!(b.spawn_position(), (2, 17));
+        for width in 4..=20 {
+            let b = GameBoard::with_hidden_rows(width, 20, 2);
+            for piece_type in [TetrisPieceType::I, TetrisPieceType::O, TetrisPieceType::T] {
+                let mut t = TetrisPiece::new(piece_type);
+                t.spawn(&b);
+                assert!(b.test_placement(&t));
+                assert!(t.cells().all(|(_, y)| t.y + y < b.hidden_rows));
+            }
+        }
+    }
+
+    #[test]
+    fn test_full_lines_and_clear() {
+        let mut b = GameBoard::new(4, 4);
+        for x in 0..4 {
+            b.set_cell(x, 3, 1);
+            b.set_cell(x, 1, 2);
+        }
+        b.set_cell(0, 2, 3);
+        b.set_cell(3, 0, 4);
+        assert_eq!(b.full_lines(), vec![1, 3]);
+        let result = b.clear_lines();
+        assert_eq!(result.rows, vec![1, 3]);
+        assert_eq!(result.cells.len(), 8);
+        assert_eq!(result.cells[0], (0, 1, 2));
+        assert!(b.full_lines().is_empty());
+        assert_eq!(b.grid(), &[0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 4, 3, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_grid_hash() {
+        let mut a = GameBoard::new(4, 4);
+        let b = GameBoard::new(4, 4);
+        assert_eq!(a.grid_hash(), b.grid_hash());
+        assert_ne!(a.grid_hash(), GameBoard::new(8, 2).grid_hash());
+        a.set_cell(1, 3, 2);
+        assert_ne!(a.grid_hash(), b.grid_hash());
+        a.set_cell(1, 3, 0);
+        assert_eq!(a.grid_hash(), b.grid_hash());
+    }
+
+    #[test]
+    fn test_text_format() {
+        let text = "
+            ..........
+            ....T.....
+            XXXTTT.XXX
+        ";
+        let b: GameBoard = text.parse().unwrap();
+        assert_eq!((b.width, b.height), (10, 3));
+        assert_eq!(b.cell(4, 1), TetrisPieceType::T.id());
+        assert_eq!(b.cell(0, 2), GARBAGE_ID);
+        assert_eq!(b.cell(6, 2), 0);
+        assert_eq!(b.to_string(), "..........\n....T.....\nXXXTTT.XXX\n");
+        assert_eq!(b.to_string().parse::<GameBoard>().unwrap().grid(), b.grid());
+
+        assert!("...\n....".parse::<GameBoard>().is_err());
+        assert!("..#".parse::<GameBoard>().is_err());
+        assert!("".parse::<GameBoard>().is_err());
+    }
+
+    #[test]
+    fn test_clear_without_gravity() {
+        let mut b = GameBoard::from_grid(3, 3, vec![1, 0, 0, 2, 2, 2, 0, 0, 3]);
+        b.clear_gravity = ClearGravity::None;
+        let result = b.clear_lines();
+        assert_eq!(result.rows, vec![1]);
+        assert_eq!(b.grid(), &[1, 0, 0, 0, 0, 0, 0, 0, 3]);
+
+        let mut b = GameBoard::new(4, 3);
+        b.clear_gravity = ClearGravity::None;
+        let mut i = TetrisPiece::new(TetrisPieceType::I);
+        (i.x, i.y) = (-1, -1);
+        b.lock_piece(&i, 9);
+        assert_eq!(b.lock_tick(0, 1), 9);
+        b.clear_lines();
+        assert!((0..4).all(|x| b.cell(x, 1) == 0 && b.lock_tick(x, 1) == 0));
+    }
+
+    #[test]
+    fn test_sticky_cascade() {
+        let mut b: GameBoard = "
+            ....
+            I...
+            IIII
+            ....
+            .OOO
+        "
+        .parse()
+        .unwrap();
+        b.clear_gravity = ClearGravity::Sticky;
+        let result = b.clear_lines();
+        assert_eq!(result.rows, vec![2, 4]);
+        assert_eq!(result.cascades, 1);
+        assert_eq!(b.grid(), &[0; 20]);
+    }
+
+    #[test]
+    fn test_lock_ticks_follow_blocks() {
+        let mut b = GameBoard::new(4, 6);
+        let mut o = TetrisPiece::new(TetrisPieceType::O);
+        o.y = 0;
+        o.x = -1;
+        b.lock_piece(&o, 7);
+        let cells: Vec<(i32, i32)> = o.cells().map(|(x, y)| (x - 1, y)).collect();
+        for (x, y) in &cells {
+            assert_eq!(b.lock_tick(*x, *y), 7);
+        }
+        for x in 0..4 {
+            b.set_cell(x, 5, 1);
+        }
+        b.clear_lines();
+        for (x, y) in &cells {
+            assert_eq!(b.lock_tick(*x, *y + 1), 7);
+        }
+    }
+
+    #[test]
+    fn test_insert_garbage_rows() {
+        let mut rng = GameRng::new(7);
+        let mut b = GameBoard::new(6, 8);
+        b.set_cell(2, 7, 1);
+        assert!(!b.insert_garbage_rows(3, 0, &mut rng));
+        assert_eq!(b.cell(2, 4), 1);
+        let hole = (0..6).find(|x| b.cell(*x, 7) == 0).unwrap();
+        for y in 5..8 {
+            assert_eq!(b.rows[y as usize].count_ones(), 5);
+            assert_eq!(b.cell(hole, y), 0);
+            assert_eq!(b.cell((hole + 1) % 6, y), GARBAGE_ID);
+        }
+        assert!(b.insert_garbage_rows(20, 100, &mut rng));
+        for y in 1..8 {
+            let hole = (0..6).find(|x| b.cell(*x, y) == 0);
+            assert_ne!(hole, (0..6).find(|x| b.cell(*x, y - 1) == 0));
+        }
+    }
+
+    #[test]
+    fn test_garbage_rows_cleared() {
+        let mut b = GameBoard::new(4, 4);
+        b.insert_garbage_rows(2, 0, &mut GameRng::new(1));
+        for y in 0..4 {
+            if let Some(hole) = (0..4).find(|x| b.cell(*x, y) == 0) {
+                b.set_cell(hole, y, 1);
             }
         }
-        full_lines
+        assert_eq!(b.clear_lines().garbage_rows(), 2);
+        assert_eq!(ClearResult::default().garbage_rows(), 0);
     }
 }
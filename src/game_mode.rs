@@ -0,0 +1,582 @@
+use crate::game_board::ClearGravity;
+use crate::high_scores::Record;
+use crate::puzzle::PuzzleGoal;
+use crate::scoring::ScoringSystem;
+use crate::scoring::TSpin;
+use crate::tetris_game::{TetrisGame, TopOutRules, GRAVITY_ONE_ROW, TICKS_PER_SECOND, TICK_MS};
+use crate::timing::{by_threshold, Timing, DEFAULT_TIMING, MASTER_TIMINGS};
+use serde::{Deserialize, Serialize};
+
+// Line goals a sprint can be played to.
+pub const SPRINT_LINES: [u32; 3] = [20, 40, 100];
+pub const ULTRA_SECONDS: u32 = 120;
+pub const MARATHON_LINES: [u32; 2] = [150, 200];
+pub const MAX_START_LEVEL: u32 = 15;
+pub const DIG_ROWS: [u32; 3] = [10, 18, 100];
+// Garbage rows on the board at once in dig mode, the rest come in as rows are dug out.
+pub const DIG_VISIBLE_ROWS: u32 = 10;
+pub const DIG_MESSINESS: u32 = 50;
+
+// Master ends on this level, its levels go up by one per piece and by one per cleared line.
+pub const MASTER_LEVELS: u32 = 999;
+
+// Master is 20G from its first piece on: a new piece falls all the way down straight away.
+const MASTER_GRAVITY_ROWS: u32 = 20;
+
+// Score needed for each master grade, from 9 up to S9. GM takes more, see master_grade.
+const MASTER_GRADES: [(u32, &str); 18] = [
+    (0, "9"),
+    (400, "8"),
+    (800, "7"),
+    (1400, "6"),
+    (2000, "5"),
+    (3500, "4"),
+    (5500, "3"),
+    (8000, "2"),
+    (12000, "1"),
+    (16000, "S1"),
+    (22000, "S2"),
+    (30000, "S3"),
+    (40000, "S4"),
+    (52000, "S5"),
+    (66000, "S6"),
+    (82000, "S7"),
+    (100000, "S8"),
+    (120000, "S9"),
+];
+const GM_SCORE: i32 = 126000;
+const GM_TIME_MS: u32 = 13 * 60_000 + 30_000;
+
+// Survival pushes up a garbage row every 8 seconds at first, each row comes 3% sooner
+// than the one before until they arrive every second.
+const SURVIVAL_START_TICKS: u32 = 8 * TICKS_PER_SECOND;
+const SURVIVAL_MIN_TICKS: u32 = TICKS_PER_SECOND;
+const SURVIVAL_SPEEDUP: (u32, u32) = (97, 100);
+const SURVIVAL_MESSINESS: u32 = 30;
+
+// A first perfect clear fills the bottom 4 rows of a 10 wide board, which takes 10 pieces.
+// Training starts each attempt from one of these setups with the first six of them in place,
+// top row first, and the next 4 pieces have to finish the clear.
+pub const PC_ROWS: i32 = 4;
+pub const PC_PIECES: u32 = 4;
+pub const PC_SETUPS: [[&str; PC_ROWS as usize]; 4] = [
+    ["SIIIIZ....", "SSOOZZ....", "JSOOZL....", "JJJLLL...."],
+    ["....SIIIIZ", "....SSOOZZ", "....JSOOZL", "....JJJLLL"],
+    ["LLL.......", "LZOOS.....", "ZZOOSSJ...", "ZIIIISJJJ."],
+    [".......JJJ", ".....ZOOSJ", "...LZZOOSS", ".LLLZIIIIS"],
+];
+
+// Ticks per row outside of marathon.
+const DEFAULT_DROP_TICKS: u32 = 30;
+
+// Guideline gravity curve, microseconds per row for levels 1 to 20. Later levels stay at 20.
+const GRAVITY_CURVE_US: [u32; 20] = gravity_curve();
+
+// (0.8 - (level - 1) * 0.007)^(level - 1) seconds per row, worked out while compiling so
+// that replays play the same on every machine.
+const fn gravity_curve() -> [u32; 20] {
+    let mut curve = [0; 20];
+    let mut level = 1;
+    while level <= 20 {
+        let base = 0.8 - (level - 1) as f64 * 0.007;
+        let mut seconds = 1.0;
+        let mut i = 1;
+        while i < level {
+            seconds *= base;
+            i += 1;
+        }
+        curve[level - 1] = (seconds * 1_000_000.0 + 0.5) as u32;
+        level += 1;
+    }
+    curve
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum GameMode {
+    Endless,
+    Sprint {
+        lines: u32,
+    },
+    Ultra {
+        seconds: u32,
+    },
+    Marathon {
+        start_level: u32,
+        lines: Option<u32>,
+    }, // no line goal plays on forever
+    Dig {
+        rows: u32,
+        messiness: u32, // chance in percent that a garbage row's hole moves
+    },
+    Survival,
+    Master,
+    Zen {
+        gravity: u32, // rows per second, 0 leaves pieces hanging until they are dropped
+    },
+    PerfectClear,
+    Puzzle {
+        number: u32, // position in the puzzle file, starting at 1
+        id: String,  // the puzzle's name as a slug, progress and replays go by it
+        goal: PuzzleGoal,
+        hold: bool,
+    },
+}
+
+impl GameMode {
+    // Key the mode's results are stored under in the high score file.
+    pub fn name(&self) -> String {
+        match self {
+            GameMode::Endless => "endless".to_string(),
+            GameMode::Sprint { lines } => format!("sprint{}", lines),
+            GameMode::Ultra { seconds } => format!("ultra{}", seconds),
+            GameMode::Marathon { lines: None, .. } => "marathon".to_string(),
+            GameMode::Marathon {
+                lines: Some(lines), ..
+            } => format!("marathon{}", lines),
+            GameMode::Dig { rows, .. } => format!("dig{}", rows),
+            GameMode::Survival => "survival".to_string(),
+            GameMode::Master => "master".to_string(),
+            GameMode::Zen { .. } => "zen".to_string(),
+            GameMode::PerfectClear => "pc".to_string(),
+            GameMode::Puzzle { id, .. } => format!("puzzle-{}", id),
+        }
+    }
+
+    pub fn top_out_rules(&self) -> TopOutRules {
+        match self {
+            GameMode::Zen { .. } => TopOutRules {
+                clear_board: true,
+                ..TopOutRules::default()
+            },
+            // A stack above the setup rows already ends the attempt and resets the board.
+            GameMode::PerfectClear => TopOutRules {
+                block_out: false,
+                lock_out: false,
+                ..TopOutRules::default()
+            },
+            _ => TopOutRules::default(),
+        }
+    }
+
+    // Whether placements can be taken back, which keeps a snapshot of every one of them.
+    pub fn allows_undo(&self) -> bool {
+        matches!(self, GameMode::Zen { .. })
+    }
+
+    // Puzzles are set up cell by cell, so a cleared row leaves the rest of the board as it
+    // was rather than dropping it.
+    pub fn clear_gravity(&self) -> ClearGravity {
+        match self {
+            GameMode::Puzzle { .. } => ClearGravity::None,
+            _ => ClearGravity::Naive,
+        }
+    }
+
+    pub fn uses_bag(&self) -> bool {
+        matches!(self, GameMode::PerfectClear)
+    }
+
+    // Rows a perfect clear attempt is built in and pieces it may take, for modes that
+    // practice them.
+    pub fn perfect_clear_attempt(&self) -> Option<(i32, u32)> {
+        match self {
+            GameMode::PerfectClear => Some((PC_ROWS, PC_PIECES)),
+            _ => None,
+        }
+    }
+
+    pub fn allows_hold(&self) -> bool {
+        !matches!(self, GameMode::Puzzle { hold: false, .. })
+    }
+
+    pub fn scoring_system(&self) -> ScoringSystem {
+        match self {
+            GameMode::Endless
+            | GameMode::Sprint { .. }
+            | GameMode::Dig { .. }
+            | GameMode::Survival => ScoringSystem::Classic,
+            GameMode::Ultra { .. }
+            | GameMode::Marathon { .. }
+            | GameMode::Zen { .. }
+            | GameMode::PerfectClear
+            | GameMode::Puzzle { .. } => ScoringSystem::Guideline,
+            GameMode::Master => ScoringSystem::Master,
+        }
+    }
+
+    pub fn start_level(&self) -> u32 {
+        match self {
+            GameMode::Marathon { start_level, .. } => *start_level,
+            GameMode::Master => 0,
+            _ => 1,
+        }
+    }
+
+    // Level after a lock that cleared `cleared` rows and brought the total up to `lines`.
+    // Marathon goes up one level every 10 lines. Master goes up with every piece, except
+    // that only a line clear gets it past the last level of a section or to the very end.
+    pub fn next_level(&self, level: u32, lines: u32, cleared: u32) -> u32 {
+        match self {
+            GameMode::Marathon { start_level, .. } => start_level + lines / 10,
+            GameMode::Master if cleared > 0 => (level + cleared).min(MASTER_LEVELS),
+            GameMode::Master if level % 100 == 99 || level + 1 == MASTER_LEVELS => level,
+            GameMode::Master => level + 1,
+            _ => level,
+        }
+    }
+
+    pub fn timing(&self, level: u32) -> Timing {
+        match self {
+            GameMode::Master => by_threshold(&MASTER_TIMINGS, level),
+            _ => DEFAULT_TIMING,
+        }
+    }
+
+    // How far the active piece falls per tick, in 1/GRAVITY_ONE_ROW rows.
+    pub fn gravity(&self, level: u32) -> u32 {
+        match self {
+            GameMode::Marathon { .. } => {
+                let us_per_row = GRAVITY_CURVE_US[(level.clamp(1, 20) - 1) as usize];
+                (GRAVITY_ONE_ROW as u64 * TICK_MS as u64 * 1000 / us_per_row as u64) as u32
+            }
+            GameMode::Zen { gravity } => GRAVITY_ONE_ROW * gravity / TICKS_PER_SECOND,
+            GameMode::Master => GRAVITY_ONE_ROW * MASTER_GRAVITY_ROWS,
+            _ => GRAVITY_ONE_ROW.div_ceil(DEFAULT_DROP_TICKS),
+        }
+    }
+
+    // Rows of garbage to dig through in total.
+    pub fn garbage_rows(&self) -> u32 {
+        match self {
+            GameMode::Dig { rows, .. } => *rows,
+            _ => 0,
+        }
+    }
+
+    pub fn messiness(&self) -> u32 {
+        match self {
+            GameMode::Dig { messiness, .. } => *messiness,
+            GameMode::Survival => SURVIVAL_MESSINESS,
+            _ => 0,
+        }
+    }
+
+    // Ticks until the next garbage row rises once `risen` rows have come up, for modes
+    // where garbage rises on a timer.
+    pub fn garbage_interval(&self, risen: u32) -> Option<u32> {
+        match self {
+            // In whole ticks a row at a time, replays have to come out the same anywhere.
+            GameMode::Survival => {
+                let (num, den) = SURVIVAL_SPEEDUP;
+                let mut ticks = SURVIVAL_START_TICKS;
+                for _ in 0..risen {
+                    if ticks <= SURVIVAL_MIN_TICKS {
+                        break;
+                    }
+                    ticks = ticks * num / den;
+                }
+                Some(ticks.max(SURVIVAL_MIN_TICKS))
+            }
+            _ => None,
+        }
+    }
+
+    pub fn goal_reached(&self, game: &TetrisGame) -> bool {
+        match self {
+            GameMode::Endless | GameMode::Ultra { .. } | GameMode::Survival => false,
+            GameMode::Sprint { lines } => game.lines >= *lines,
+            GameMode::Marathon { lines, .. } => lines.is_some_and(|lines| game.lines >= lines),
+            GameMode::Dig { rows, .. } => game.garbage_cleared >= *rows,
+            GameMode::Master => game.level >= MASTER_LEVELS,
+            GameMode::Zen { .. } | GameMode::PerfectClear => false,
+            GameMode::Puzzle { goal, .. } => match goal {
+                PuzzleGoal::Lines(lines) => game.lines >= *lines,
+                PuzzleGoal::PerfectClear => game.lines > 0 && game.board.is_empty(),
+                PuzzleGoal::TSpinTriple => game
+                    .last_clear
+                    .is_some_and(|clear| clear.t_spin == TSpin::Full && clear.lines == 3),
+            },
+        }
+    }
+
+    pub fn time_up(&self, game: &TetrisGame) -> bool {
+        match self {
+            GameMode::Endless
+            | GameMode::Sprint { .. }
+            | GameMode::Marathon { .. }
+            | GameMode::Dig { .. }
+            | GameMode::Survival
+            | GameMode::Master
+            | GameMode::Zen { .. }
+            | GameMode::PerfectClear
+            | GameMode::Puzzle { .. } => false,
+            GameMode::Ultra { seconds } => game.elapsed_ms() >= seconds * 1000,
+        }
+    }
+
+    // Whether a finished game counts as a result at all, a sprint has to reach its goal.
+    pub fn is_complete(&self, game: &TetrisGame) -> bool {
+        match self {
+            GameMode::Endless
+            | GameMode::Marathon { lines: None, .. }
+            | GameMode::Survival
+            | GameMode::Master => true,
+            GameMode::Sprint { .. }
+            | GameMode::Marathon { .. }
+            | GameMode::Dig { .. }
+            | GameMode::Puzzle { .. } => self.goal_reached(game),
+            GameMode::Ultra { .. } => self.time_up(game),
+            GameMode::Zen { .. } | GameMode::PerfectClear => false,
+        }
+    }
+
+    pub fn is_better(&self, record: &Record, best: &Record) -> bool {
+        match self {
+            GameMode::Endless
+            | GameMode::Ultra { .. }
+            | GameMode::Marathon { .. }
+            | GameMode::Master
+            | GameMode::Zen { .. }
+            | GameMode::PerfectClear => record.score > best.score,
+            GameMode::Sprint { .. } | GameMode::Dig { .. } | GameMode::Puzzle { .. } => {
+                record.time_ms < best.time_ms
+            }
+            GameMode::Survival => record.time_ms > best.time_ms,
+        }
+    }
+
+    // Lines for the stats panel next to the board, compared against the best result.
+    pub fn stats(&self, game: &TetrisGame, best: Option<&Record>) -> Vec<String> {
+        match self {
+            GameMode::Endless => vec![format!("LINES {}", game.lines)],
+            GameMode::Sprint { lines } => {
+                let elapsed = game.elapsed_ms();
+                let mut stats = vec![
+                    format!("TIME {}", format_time(elapsed)),
+                    format!("LEFT {}", lines.saturating_sub(game.lines)),
+                ];
+                if let Some(best) = best {
+                    stats.push(format!("PB   {}", format_time(best.time_ms)));
+                    if let Some(split) = game.line_times.last() {
+                        if let Some(best_split) = best.splits.get(game.line_times.len() - 1) {
+                            stats.push(format!("PACE {}", format_delta(*split, *best_split)));
+                        }
+                    }
+                    let ghost_lines = best.splits.iter().filter(|t| **t <= elapsed).count();
+                    stats.push(format!(
+                        "GHOST {}",
+                        lines.saturating_sub(ghost_lines as u32)
+                    ));
+                }
+                stats
+            }
+            GameMode::Ultra { seconds } => {
+                let remaining = (seconds * 1000).saturating_sub(game.elapsed_ms());
+                let mut stats = vec![
+                    format!("TIME {}", format_time(remaining)),
+                    format!("LINES {}", game.lines),
+                ];
+                if let Some(best) = best {
+                    stats.push(format!("PB {}", best.score));
+                }
+                stats.extend(clear_stats(game));
+                stats
+            }
+            GameMode::Marathon { lines, .. } => {
+                let mut stats = vec![
+                    format!("LEVEL {}", game.level),
+                    format!("LINES {}", game.lines),
+                    format!("TIME {}", format_time(game.elapsed_ms())),
+                ];
+                if let Some(lines) = lines {
+                    stats.insert(2, format!("GOAL {}", lines));
+                }
+                if let Some(best) = best {
+                    stats.push(format!("PB {}", best.score));
+                }
+                stats.extend(clear_stats(game));
+                stats
+            }
+            GameMode::Dig { rows, .. } => {
+                let mut stats = vec![
+                    format!("TIME {}", format_time(game.elapsed_ms())),
+                    format!("LEFT {}", rows.saturating_sub(game.garbage_cleared)),
+                ];
+                if let Some(best) = best {
+                    stats.push(format!("PB   {}", format_time(best.time_ms)));
+                }
+                stats
+            }
+            GameMode::Survival => {
+                let mut stats = vec![
+                    format!("TIME {}", format_time(game.elapsed_ms())),
+                    format!("LINES {}", game.lines),
+                    format!("RISEN {}", game.garbage_risen),
+                ];
+                if let Some(best) = best {
+                    stats.push(format!("PB   {}", format_time(best.time_ms)));
+                }
+                stats
+            }
+            GameMode::Master => {
+                let section_end = ((game.level / 100 + 1) * 100).min(MASTER_LEVELS);
+                let mut stats = vec![
+                    format!("LEVEL {}/{}", game.level, section_end),
+                    format!("TIME {}", format_time(game.elapsed_ms())),
+                    format!(
+                        "GRADE {}",
+                        master_grade(game.score, game.level, game.elapsed_ms())
+                    ),
+                ];
+                if let Some(best) = best {
+                    stats.push(format!("PB {}", best.score));
+                }
+                let mut section_start = 0;
+                for (i, time) in game.section_times.iter().enumerate() {
+                    stats.push(format!("S{} {}", i + 1, format_time(time - section_start)));
+                    section_start = *time;
+                }
+                stats
+            }
+            GameMode::Zen { .. } => {
+                let mut stats = vec![
+                    format!("LINES {}", game.lines),
+                    format!("PIECES {}", game.stats.pieces),
+                    format!("UNDO {}", game.undo_count()),
+                ];
+                if game.chosen_next().is_some() {
+                    stats.push("NEXT CHOSEN".to_string());
+                }
+                stats.extend(clear_stats(game));
+                stats
+            }
+            GameMode::PerfectClear => {
+                let pc = game.perfect_clears;
+                vec![
+                    format!("PC {}/{}", pc.successes, pc.attempts),
+                    format!("RATE {}%", pc.success_rate()),
+                    format!("STREAK {}", pc.streak),
+                    format!("BEST {}", pc.best_streak),
+                    format!("PIECE {}/{}", game.attempt_pieces + 1, PC_PIECES),
+                ]
+            }
+            GameMode::Puzzle { number, goal, .. } => {
+                let mut stats = vec![
+                    format!("PUZZLE {}", number),
+                    format!("{}", goal),
+                    format!("PIECES {}", game.pieces_left().unwrap_or(0) + 1),
+                    format!("TIME {}", format_time(game.elapsed_ms())),
+                ];
+                if let Some(best) = best {
+                    stats.push(format!("SOLVED {}", format_time(best.time_ms)));
+                }
+                stats
+            }
+        }
+    }
+}
+
+// Grade for a master game, from the score alone up to S9. GM needs the S9 score and the
+// final level within the time limit.
+pub fn master_grade(score: i32, level: u32, time_ms: u32) -> &'static str {
+    if level >= MASTER_LEVELS && score >= GM_SCORE && time_ms <= GM_TIME_MS {
+        return "GM";
+    }
+    by_threshold(&MASTER_GRADES, score.max(0) as u32)
+}
+
+// The last special clear along with the running combo and back-to-back state.
+fn clear_stats(game: &TetrisGame) -> Vec<String> {
+    let mut stats = Vec::new();
+    if let Some(clear) = game.last_clear {
+        stats.push(clear.name());
+        if clear.back_to_back {
+            stats.push("BACK-TO-BACK".to_string());
+        }
+    }
+    if game.scoring.combo > 0 {
+        stats.push(format!("COMBO {}", game.scoring.combo));
+    }
+    stats
+}
+
+// Formats a duration as m:ss.mmm.
+pub fn format_time(ms: u32) -> String {
+    format!("{}:{:02}.{:03}", ms / 60_000, ms / 1000 % 60, ms % 1000)
+}
+
+// Formats how far ahead (-) or behind (+) a split is compared to the best one.
+pub fn format_delta(ms: u32, best_ms: u32) -> String {
+    let sign = if ms > best_ms { '+' } else { '-' };
+    let delta = ms.abs_diff(best_ms);
+    format!("{}{}.{:03}", sign, delta / 1000, delta % 1000)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_perfect_clear_setups() {
+        for setup in PC_SETUPS {
+            // Six pieces are down and no row is full yet.
+            let blocks = setup.concat().chars().filter(|c| *c != '.').count();
+            assert_eq!(blocks, 24);
+            assert!(setup.iter().all(|row| row.len() == 10 && row.contains('.')));
+        }
+    }
+
+    #[test]
+    fn test_marathon_levels() {
+        let mode = GameMode::Marathon {
+            start_level: 3,
+            lines: Some(150),
+        };
+        assert_eq!(mode.start_level(), 3);
+        assert_eq!(mode.next_level(4, 29, 1), 5);
+        assert_eq!(mode.gravity(1), GRAVITY_ONE_ROW / 200);
+        assert_eq!(GRAVITY_CURVE_US[2], 617_796);
+        assert_eq!(GRAVITY_CURVE_US[13], 11_439);
+        assert_eq!(GRAVITY_CURVE_US[19], 455);
+        assert_eq!(mode.gravity(20), GRAVITY_ONE_ROW * 5000 / 455);
+        assert_eq!(mode.gravity(25), mode.gravity(20));
+        assert!(mode.gravity(10) < mode.gravity(11));
+        assert_eq!(GameMode::Endless.gravity(1) * 30 / GRAVITY_ONE_ROW, 1);
+    }
+
+    #[test]
+    fn test_master_levels_and_grades() {
+        let mode = GameMode::Master;
+        assert_eq!(mode.next_level(0, 0, 0), 1);
+        assert_eq!(mode.next_level(99, 0, 0), 99);
+        assert_eq!(mode.next_level(99, 1, 1), 100);
+        assert_eq!(mode.next_level(997, 0, 0), 998);
+        assert_eq!(mode.next_level(998, 0, 0), 998);
+        assert_eq!(mode.next_level(998, 4, 4), MASTER_LEVELS);
+        assert_eq!(mode.gravity(0), GRAVITY_ONE_ROW * 20);
+        assert_eq!(mode.gravity(500), GRAVITY_ONE_ROW * 20);
+        assert_eq!(master_grade(0, 10, 1000), "9");
+        assert_eq!(master_grade(16000, 400, 1000), "S1");
+        assert_eq!(master_grade(130000, 998, 1000), "S9");
+        assert_eq!(master_grade(130000, 999, GM_TIME_MS), "GM");
+        assert_eq!(master_grade(130000, 999, GM_TIME_MS + 1), "S9");
+    }
+
+    #[test]
+    fn test_survival_speeds_up() {
+        let mode = GameMode::Survival;
+        assert_eq!(mode.garbage_interval(0), Some(SURVIVAL_START_TICKS));
+        assert_eq!(mode.garbage_interval(1), Some(1552));
+        assert_eq!(mode.garbage_interval(2), Some(1505));
+        assert!(mode.garbage_interval(10) < mode.garbage_interval(9));
+        assert_eq!(mode.garbage_interval(1000), Some(SURVIVAL_MIN_TICKS));
+        assert_eq!(GameMode::Endless.garbage_interval(0), None);
+    }
+
+    #[test]
+    fn test_format_time() {
+        assert_eq!(format_time(83_456), "1:23.456");
+        assert_eq!(format_delta(10_250, 10_000), "+0.250");
+        assert_eq!(format_delta(9_000, 10_500), "-1.500");
+    }
+}
@@ -0,0 +1,182 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::scoring::GameStats;
+
+pub const HIGH_SCORE_FILE: &str = "highscores.txt";
+
+// Result of a finished game, splits hold the time in ms at which each line was cleared.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Record {
+    pub score: i32,
+    pub lines: u32,
+    pub time_ms: u32,
+    pub splits: Vec<u32>,
+    pub stats: GameStats,
+}
+
+// Best result per mode, stored as one `mode key=value key=value ...` line each.
+#[derive(Debug)]
+pub struct HighScores {
+    path: PathBuf,
+    records: BTreeMap<String, Record>,
+}
+
+impl HighScores {
+    // Loads the high score file, a missing or unreadable file starts an empty table.
+    pub fn load(path: impl Into<PathBuf>) -> HighScores {
+        let path = path.into();
+        let mut records = BTreeMap::new();
+        if let Ok(contents) = fs::read_to_string(&path) {
+            for line in contents.lines() {
+                if let Some((mode, record)) = parse_record(line) {
+                    records.insert(mode, record);
+                }
+            }
+        }
+        HighScores { path, records }
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        let mut contents = String::new();
+        for (mode, record) in &self.records {
+            let splits: Vec<String> = record.splits.iter().map(|s| s.to_string()).collect();
+            contents.push_str(&format!(
+                "{} score={} lines={} time_ms={} splits={} pieces={} tetrises={} t_spins={} max_combo={}\n",
+                mode,
+                record.score,
+                record.lines,
+                record.time_ms,
+                splits.join(","),
+                record.stats.pieces,
+                record.stats.tetrises,
+                record.stats.t_spins,
+                record.stats.max_combo,
+            ));
+        }
+        fs::write(&self.path, contents).map_err(|e| e.to_string())
+    }
+
+    pub fn best(&self, mode: &str) -> Option<&Record> {
+        self.records.get(mode)
+    }
+
+    // Keeps the record if there is none for the mode yet or it beats the current one.
+    pub fn submit(
+        &mut self,
+        mode: &str,
+        record: Record,
+        is_better: impl Fn(&Record, &Record) -> bool,
+    ) -> bool {
+        match self.records.get(mode) {
+            Some(best) if !is_better(&record, best) => false,
+            _ => {
+                self.records.insert(mode.to_string(), record);
+                true
+            }
+        }
+    }
+}
+
+// Unknown keys are skipped so files written by newer versions still load.
+fn parse_record(line: &str) -> Option<(String, Record)> {
+    let mut fields = line.split_whitespace();
+    let mode = fields.next()?.to_string();
+    if !line.contains('=') {
+        return Some((mode, parse_positional(fields)?));
+    }
+    let mut record = Record::default();
+    for field in fields {
+        let (key, value) = field.split_once('=')?;
+        match key {
+            "score" => record.score = value.parse().ok()?,
+            "lines" => record.lines = value.parse().ok()?,
+            "time_ms" => record.time_ms = value.parse().ok()?,
+            "splits" => {
+                record.splits = value
+                    .split(',')
+                    .filter(|s| !s.is_empty())
+                    .map(|s| s.parse().ok())
+                    .collect::<Option<Vec<u32>>>()?
+            }
+            "pieces" => record.stats.pieces = value.parse().ok()?,
+            "tetrises" => record.stats.tetrises = value.parse().ok()?,
+            "t_spins" => record.stats.t_spins = value.parse().ok()?,
+            "max_combo" => record.stats.max_combo = value.parse().ok()?,
+            _ => {}
+        }
+    }
+    Some((mode, record))
+}
+
+// Files from before the stats were kept have `mode score lines time_ms split,split,...`
+// lines.
+fn parse_positional<'a>(mut fields: impl Iterator<Item = &'a str>) -> Option<Record> {
+    Some(Record {
+        score: fields.next()?.parse().ok()?,
+        lines: fields.next()?.parse().ok()?,
+        time_ms: fields.next()?.parse().ok()?,
+        splits: fields
+            .next()
+            .unwrap_or("")
+            .split(',')
+            .filter(|s| !s.is_empty())
+            .map(|s| s.parse().ok())
+            .collect::<Option<Vec<u32>>>()?,
+        stats: GameStats::default(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_save_and_load() {
+        let path = std::env::temp_dir().join("tetris_test_highscores.txt");
+        let _ = fs::remove_file(&path);
+        let mut high_scores = HighScores::load(&path);
+        let record = Record {
+            score: 1200,
+            lines: 3,
+            time_ms: 4500,
+            splits: vec![1000, 3000, 4500],
+            stats: GameStats {
+                pieces: 10,
+                tetrises: 0,
+                t_spins: 1,
+                max_combo: 2,
+            },
+        };
+        assert!(high_scores.submit("sprint3", record.clone(), |a, b| a.time_ms < b.time_ms));
+        let slower = Record {
+            time_ms: 5000,
+            ..record.clone()
+        };
+        assert!(!high_scores.submit("sprint3", slower, |a, b| a.time_ms < b.time_ms));
+        high_scores.save().unwrap();
+
+        let loaded = HighScores::load(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.best("sprint3"), Some(&record));
+        assert_eq!(loaded.best("endless"), None);
+    }
+
+    #[test]
+    fn test_load_old_format() {
+        let (mode, record) = parse_record("sprint40 0 40 61250 1500,3000").unwrap();
+        assert_eq!(mode, "sprint40");
+        assert_eq!(
+            record,
+            Record {
+                lines: 40,
+                time_ms: 61250,
+                splits: vec![1500, 3000],
+                ..Record::default()
+            }
+        );
+        assert_eq!(parse_record("endless 5000 12 90000").unwrap().1.score, 5000);
+        assert!(parse_record("endless 5000 twelve").is_none());
+    }
+}
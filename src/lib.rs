@@ -0,0 +1,27 @@
+// The game engine and its frontends, used by the `tetris` binary and the benchmarks.
+#[cfg(feature = "sdl")]
+pub mod frame_export;
+pub mod frontend;
+pub mod fumen;
+pub mod game_board;
+pub mod game_mode;
+pub mod high_scores;
+pub mod options;
+pub mod puzzle;
+pub mod replay;
+pub mod rng;
+pub mod save;
+pub mod scoring;
+#[cfg(feature = "sdl")]
+pub mod sdl_frontend;
+#[cfg(feature = "sdl")]
+pub mod software_renderer;
+pub mod tetris_game;
+#[cfg(feature = "sdl")]
+pub mod tetris_game_graphics;
+pub mod tetris_piece;
+pub mod timing;
+pub mod tui;
+
+use crate::game_board::GameBoard;
+use crate::tetris_piece::{TetrisPiece, TetrisPieceState};
@@ -1,238 +1,335 @@
-extern crate sdl2;
+#[cfg(feature = "sdl")]
+use sdl2::image::InitFlag;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+#[cfg(feature = "sdl")]
+use std::path::PathBuf;
+#[cfg(feature = "sdl")]
+use tetris::frame_export::{frame_tick, FrameExport, DEFAULT_FPS};
+use tetris::frontend::Session;
+use tetris::game_mode::{format_time, master_grade, GameMode};
+use tetris::high_scores::{HighScores, Record, HIGH_SCORE_FILE};
+#[cfg(feature = "sdl")]
+use tetris::options::parse_number;
+use tetris::options::{parse_options, take_arg, Options};
+use tetris::replay::{Replay, ReplayPlayer};
+use tetris::save::{SavedGame, SAVE_FILE};
+#[cfg(feature = "sdl")]
+use tetris::sdl_frontend::{open_window, ScaleMode, SdlInput, SdlRenderer};
+#[cfg(feature = "sdl")]
+use tetris::software_renderer::SoftwareRenderer;
+use tetris::tetris_game::TetrisGame;
+#[cfg(feature = "sdl")]
+use tetris::tetris_game_graphics::Layout;
+use tetris::tui;
 
-use sdl2::event::Event;
-use sdl2::image::{InitFlag, LoadSurface};
-use sdl2::keyboard::Keycode;
-use sdl2::rect::Rect;
-use sdl2::surface::Surface;
-use tetris_piece::TetrisPieceType;
-use std::time::Duration;
+// Plays a replay through without opening a window and checks that it ends as recorded,
+// so submitted results can be checked. A mismatch makes main return an error.
+fn verify_replay(path: &str) -> Result<(), String> {
+    let replay = Replay::load(path)?;
+    let options = parse_options(&replay.args)?;
+    let mut game = options.new_game()?;
+    let mut player = ReplayPlayer::new(replay, &game);
+    let result = player.verify(&mut game);
+    println!("score {}", game.score);
+    println!("lines {}", game.lines);
+    println!("time {}", format_time(game.elapsed_ms()));
+    println!("board {:016x}", game.board.grid_hash());
+    result
+}
 
-mod game_board;
-mod tetris_game_graphics;
-mod tetris_piece;
-use crate::game_board::GameBoard;
-use crate::tetris_game_graphics::{SpriteSheet, TetrisGameGraphics};
-use crate::tetris_piece::{TetrisPiece, TetrisPieceState};
+// Draws the start of a game into a PNG without opening a window, e.g. a thumbnail of
+// a puzzle: `tetris render --out puzzle3.png --mode puzzle --puzzle 3`.
+#[cfg(feature = "sdl")]
+fn render_game(mut args: Vec<String>) -> Result<(), String> {
+    let out = take_arg(&mut args, "--out")?.ok_or("render needs --out <file.png>")?;
+    let options = parse_options(&args)?;
+    let game = options.new_game()?;
+    let _image_context = sdl2::image::init(InitFlag::PNG)?;
+    SoftwareRenderer::load()?.save_png(&game, &game.mode.stats(&game, None), &out)?;
+    println!("Saved {}", out);
+    Ok(())
+}
 
-fn main() -> Result<(), String> {
+// Plays a replay through offscreen and writes a frame of it every 1/fps seconds of game
+// time: `tetris render-replay <file> --out frames/ --gif run.gif --fps 30`.
+#[cfg(feature = "sdl")]
+fn render_replay(mut args: Vec<String>) -> Result<(), String> {
+    let out = take_arg(&mut args, "--out")?;
+    let gif = take_arg(&mut args, "--gif")?;
+    let fps = match take_arg(&mut args, "--fps")? {
+        Some(value) => parse_number("--fps", &value, 1..=50)? as u32,
+        None => DEFAULT_FPS,
+    };
+    if out.is_none() && gif.is_none() {
+        return Err("render-replay needs --out <dir> or --gif <file.gif>".to_string());
+    }
+    let replay = Replay::load(args.first().ok_or("render-replay needs a replay file")?)?;
+    let options = parse_options(&replay.args)?;
+    let mut game = options.new_game()?;
+    let mut player = ReplayPlayer::new(replay, &game);
+    let _image_context = sdl2::image::init(InitFlag::PNG)?;
+    let mut renderer = SoftwareRenderer::load()?;
+    let layout = Layout::new(&game.board);
+    let mut export = FrameExport::new(
+        out.map(PathBuf::from),
+        gif.as_deref().map(Path::new),
+        fps,
+        layout.width as u32,
+        layout.height as u32,
+    )?;
+    loop {
+        export.add(&renderer.render(&game, &game.mode.stats(&game, None))?)?;
+        if player.is_finished(&game) {
+            break;
+        }
+        let tick = frame_tick(export.frames, fps);
+        while game.ticks < tick && !player.is_finished(&game) {
+            player.step(&mut game);
+        }
+    }
+    println!(
+        "Wrote {} frames of {}",
+        export.frames,
+        format_time(game.elapsed_ms())
+    );
+    export.finish()
+}
+
+// Plays in a window scaled by `--scale`, or fills the screen with `--fullscreen`.
+#[cfg(feature = "sdl")]
+fn play_in_window(
+    session: &mut Session,
+    replaying: bool,
+    scale: Option<&str>,
+    fullscreen: bool,
+) -> Result<(), String> {
+    let scale_mode = match scale {
+        Some(value) => value.parse()?,
+        None => ScaleMode::Integer,
+    };
     let sdl_context = sdl2::init()?;
-    let sdl_timer = sdl_context.timer()?;
     let video_subsystem = sdl_context.video()?;
     let _image_context = sdl2::image::init(InitFlag::PNG | InitFlag::JPG)?;
-    let mut render_width = 480;
-    let mut render_height = 400;
-    let mut sprite_width_mult = 1;
-    let mut sprite_height_mult = 1;
-    let line_scores = vec![0, 100, 200, 400, 800];
+    let layout = Layout::new(&session.game.board);
+    let mut canvas = open_window(&video_subsystem, &layout, scale_mode, fullscreen)?;
+    let texture_creator = canvas.texture_creator();
+    let mut renderer = SdlRenderer::new(&mut canvas, &texture_creator, layout)?;
+    let mut input = SdlInput::new(sdl_context.event_pump()?, replaying);
+    session.run(&mut input, &mut renderer)
+}
 
-    let display_mode = video_subsystem.current_display_mode(0).unwrap();
-    if display_mode.w > render_width && display_mode.h > render_height {
-        sprite_width_mult = 2;
-        sprite_height_mult = 2;
-        render_width *= 2;
-        render_height *= 2;
-    }
+#[cfg(not(feature = "sdl"))]
+fn play_in_window(_: &mut Session, _: bool, _: Option<&str>, _: bool) -> Result<(), String> {
+    Err("this build has no window, play in the terminal with `tetris tui`".to_string())
+}
 
-    let window = video_subsystem
-        .window("Clonetris", render_width as u32, render_height as u32)
-        .position_centered()
-        .build()
+// Asks on the terminal whether to carry on with the game saved on quit, which brings the
+// options it was started with. The save stays until the resumed game is running, saying
+// no leaves it for the next start unless the new game is quit and saved over it.
+fn offer_resume() -> Result<Option<(SavedGame, Options)>, String> {
+    if !Path::new(SAVE_FILE).exists() {
+        return Ok(None);
+    }
+    let loaded = SavedGame::load(SAVE_FILE)
+        .and_then(|saved| Ok((parse_options(&saved.recording.args)?, saved)));
+    let (options, saved) = match loaded {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            println!("Can't resume the saved game: {}", e);
+            return Ok(None);
+        }
+    };
+    print!(
+        "Resume the saved {} game at {}? [Y/n] ",
+        saved.game.mode.name(),
+        format_time(saved.game.elapsed_ms())
+    );
+    io::stdout().flush().map_err(|e| e.to_string())?;
+    let mut answer = String::new();
+    io::stdin()
+        .read_line(&mut answer)
         .map_err(|e| e.to_string())?;
+    Ok(match answer.trim() {
+        "" | "y" | "Y" | "yes" => Some((saved, options)),
+        _ => None,
+    })
+}
 
-    let mut canvas = window.into_canvas().build().map_err(|e| e.to_string())?;
-
-    let mut event_pump = sdl_context.event_pump()?;
-    let mut last_ticks: u32;
-
-    let texture_creator = canvas.texture_creator();
-    let spritesheet_surface = Surface::from_file("base_gfx.png").map_err(|e| e.to_string())?;
-    let background_surface = Surface::from_file("background_gfx.png").map_err(|e| e.to_string())?;
-    let foreground_surface = Surface::from_file("foreground_gfx.png").map_err(|e| e.to_string())?;
-    let sprite_sheet = SpriteSheet::new_from_surface_with_rect(
-        &texture_creator,
-        &spritesheet_surface,
-        16,
-        16,
-        Rect::new(0, 0, 16 * 7, 16),
-    );
-    let font_sheet = SpriteSheet::new_from_surface_with_rect(
-        &texture_creator,
-        &spritesheet_surface,
-        6,
-        6,
-        Rect::new(0, 16, 6 * 36, 6),
-    );
-    let shine_effect_sheet = SpriteSheet::new_from_surface_with_rect(
-        &texture_creator,
-        &spritesheet_surface,
-        16,
-        16,
-        Rect::new(0, 64, 16 * 7, 16),
-    );
+// Saves a game that was quit before it ended so it can be resumed, otherwise saves its
+// replay and submits the result.
+fn end_game(
+    game: TetrisGame,
+    mut recording: Replay,
+    mut high_scores: HighScores,
+    high_score_key: &str,
+) -> Result<(), String> {
+    if game.game_over.is_none() {
+        SavedGame::new(&game, &recording).save(SAVE_FILE)?;
+        println!("Game saved, it can be resumed on the next start");
+        return Ok(());
+    }
+    recording.finish(&game);
+    let replay_path = Replay::path(&game);
+    recording.save(&replay_path)?;
+    println!("Replay saved to {}", replay_path.display());
 
-    let mut board: GameBoard = GameBoard::new(10, 22);
-    let mut t = TetrisPiece::new_random_piece();
-    let mut t_next = TetrisPiece::new_random_piece();
-    let mut t_hold = TetrisPiece::new(TetrisPieceType::None);
-    let mut gfx = TetrisGameGraphics::new(&mut canvas, sprite_width_mult, sprite_height_mult);
-    let drop_time: u32 = 30;
-    let place_time: u32 = 100;
-    let mut drop_timer: u32 = drop_time;
-    let mut place_timer: u32 = place_time;
-    let mut score = 0;
-    let mut shine_frame = 0;
-    let mut delta_time: u32;
-    'running: loop {
-        let current_ticks: u32 = sdl_timer.ticks();
-        last_ticks = current_ticks;
+    if game.mode == GameMode::PerfectClear {
+        let pc = game.perfect_clears;
+        println!(
+            "{} perfect clears in {} attempts ({}%), best streak {}",
+            pc.successes,
+            pc.attempts,
+            pc.success_rate(),
+            pc.best_streak
+        );
+    }
 
-        for event in event_pump.poll_iter() {
-            match event {
-                Event::Quit { .. }
-                | Event::KeyDown {
-                    keycode: Some(Keycode::Escape),
-                    ..
-                } => {
-                    break 'running;
-                }
-                Event::KeyDown {
-                    keycode: Some(Keycode::Left),
-                    ..
-                } => {
-                    t.translate(&board, -1, 0);
-                }
-                Event::KeyDown {
-                    keycode: Some(Keycode::Right),
-                    ..
-                } => {
-                    t.translate(&board, 1, 0);
-                }
-                Event::KeyDown {
-                    keycode: Some(Keycode::Down),
-                    ..
-                } => {
-                    t.translate(&board, 0, 1);
-                }
-                Event::KeyDown {
-                    keycode: Some(Keycode::D),
-                    ..
-                } => {
-                    t.srs_rotate(&board, true);
-                }
-                Event::KeyDown {
-                    keycode: Some(Keycode::A),
-                    ..
-                } => {
-                    t.srs_rotate(&board, false);
-                }
-                Event::KeyDown { keycode: Some(Keycode::Q), .. } => {
-                    if t.state == TetrisPieceState::Placing {
-                        continue;
-                    }
-                    if t_hold.piece_type == TetrisPieceType::None {
-                        t_hold = t;
-                        t = t_next;
-                        t_next = TetrisPiece::new_random_piece();
-                    } else {
-                        t_hold.y = t.y;
-                        t_hold.x = t.x;
-                        
-                        let temp = t_hold;
-                        t_hold = t;
-                        for _i in 0..t_hold.rotation {
-                            t_hold.rotate_ccw();
-                        }
-                        t = temp;
-                    }
-                }
-                Event::KeyDown {
-                    keycode: Some(Keycode::Space),
-                    ..
-                } => {
-                    while t.state == TetrisPieceState::Active {
-                        let old_y = t.y;
-                        t.translate(&board, 0, 1);
-                        if t.y == old_y {
-                            t.state = TetrisPieceState::Placing;
-                        }
-                    }
-                }
-                _ => {}
+    if game.game_over.is_some() && game.mode.is_complete(&game) {
+        let record = Record {
+            score: game.score,
+            lines: game.lines,
+            time_ms: game.elapsed_ms(),
+            splits: game.line_times.clone(),
+            stats: game.stats,
+        };
+        match game.mode {
+            GameMode::Sprint { lines } => {
+                println!("{} lines in {}", lines, format_time(record.time_ms))
             }
-        }
-
-        drop_timer -= 1;
-        if t.state == TetrisPieceState::Placing {
-            place_timer -= 1;
-        }
-
-        if drop_timer == 0 {
-            let old_y = t.y;
-            t.translate(&board, 0, 1);
-            if t.y == old_y {
-                t.state = TetrisPieceState::Placing;
-            } else {
-                t.state = TetrisPieceState::Active;
-                place_timer = place_time;
+            GameMode::Ultra { .. } | GameMode::Marathon { .. } => println!(
+                "Score {} with {} lines, {} pieces, {} tetrises, {} T-spins, max combo {}",
+                record.score,
+                record.lines,
+                record.stats.pieces,
+                record.stats.tetrises,
+                record.stats.t_spins,
+                record.stats.max_combo
+            ),
+            GameMode::Dig { rows, .. } => {
+                println!("{} garbage rows in {}", rows, format_time(record.time_ms))
+            }
+            GameMode::Survival => println!(
+                "Survived {} with {} lines",
+                format_time(record.time_ms),
+                record.lines
+            ),
+            GameMode::Master => println!(
+                "Grade {} at level {} in {}",
+                master_grade(record.score, game.level, record.time_ms),
+                game.level,
+                format_time(record.time_ms)
+            ),
+            GameMode::Puzzle { number, .. } => {
+                println!(
+                    "Solved puzzle {} in {}",
+                    number,
+                    format_time(record.time_ms)
+                )
             }
-            drop_timer = drop_time;
+            GameMode::Endless | GameMode::Zen { .. } | GameMode::PerfectClear => {}
         }
+        let mode = &game.mode;
+        if high_scores.submit(high_score_key, record, |a, b| mode.is_better(a, b)) {
+            println!("New personal best!");
+            high_scores.save()?;
+        }
+    }
 
-        if place_timer == 0 && t.state == TetrisPieceState::Placing {
-            t.state = TetrisPieceState::Placed;
-            board.place_piece(&t);
-            let full_lines = board.full_lines();
-
-            score += line_scores[full_lines.len() as usize];
+    Ok(())
+}
 
-            for line in full_lines {
-                for y in (0..line).rev() {
-                    for x in 0..10 {
-                        board.grid[(x + (y + 1) * 10) as usize] = board.grid[(x + y * 10) as usize];
-                    }
-                }
-                for x in 0..10 {
-                    board.grid[x] = 0;
+fn main() -> Result<(), String> {
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("verify-replay") {
+        return verify_replay(args.get(1).ok_or("verify-replay needs a replay file")?);
+    }
+    #[cfg(feature = "sdl")]
+    match args.first().map(String::as_str) {
+        Some("render") => return render_game(args[1..].to_vec()),
+        Some("render-replay") => return render_replay(args[1..].to_vec()),
+        _ => {}
+    }
+    #[cfg(not(feature = "sdl"))]
+    if let Some("render" | "render-replay") = args.first().map(String::as_str) {
+        return Err("this build can't draw pictures, it needs the sdl feature".to_string());
+    }
+    // `tetris tui [options]` plays in the terminal instead of a window.
+    let terminal = args.first().map(String::as_str) == Some("tui");
+    if terminal {
+        args.remove(0);
+    }
+    // How the window is scaled, these aren't game options so replays don't keep them.
+    let scale = take_arg(&mut args, "--scale")?;
+    let fullscreen = match args.iter().position(|arg| arg == "--fullscreen") {
+        Some(i) => {
+            args.remove(i);
+            true
+        }
+        None => false,
+    };
+    // `tetris replay <file>` plays a recorded game back instead of starting a new one.
+    let mut replay = None;
+    let mut saved = None;
+    let options = match args.first().map(String::as_str) {
+        Some("replay") => {
+            let path = args.get(1).ok_or("replay needs a replay file")?;
+            let loaded = Replay::load(path)?;
+            let options = parse_options(&loaded.args)?;
+            replay = Some(loaded);
+            options
+        }
+        _ => match offer_resume()? {
+            Some((resumed, options)) => {
+                if !args.is_empty() {
+                    println!("Resuming the saved game, ignoring {}", args.join(" "));
                 }
+                saved = Some(resumed);
+                options
             }
-
-            t = t_next;
-            t_next = TetrisPiece::new_random_piece();
-            if !board.test_placement(&t) {
-                break 'running;
-            }
-            place_timer = place_time;
+            None => parse_options(&args)?,
+        },
+    };
+    let mut recording = Replay {
+        args: options.args.clone(),
+        ..Replay::default()
+    };
+    let game = match saved {
+        Some(saved) => {
+            // A game is resumed once, quitting it again saves it anew.
+            fs::remove_file(SAVE_FILE).map_err(|e| e.to_string())?;
+            recording = saved.recording;
+            saved.game
         }
+        None => options.new_game()?,
+    };
+    if let Some(puzzle) = &options.puzzle {
+        println!("Puzzle: {} ({})", puzzle.name, puzzle.goal);
+    }
+    let player = replay.map(|replay| ReplayPlayer::new(replay, &game));
+    let replaying = player.is_some();
+    let high_scores = HighScores::load(HIGH_SCORE_FILE);
+    let high_score_key = options.high_score_key();
+    let best = high_scores.best(&high_score_key).cloned();
+    let mut session = Session::new(game, recording, player, best);
 
-        gfx.draw_background(vec![&background_surface]);
-        gfx.draw_game_board(160, 16, &board, &sprite_sheet);
-        gfx.draw_piece(160 + (&t.x * 16), 16 + (&t.y * 16), &t, &sprite_sheet);
-        if &t.state == &TetrisPieceState::Placing {
-            gfx.draw_shine_effect(
-                160 + (&t.x * 16),
-                16 + (&t.y * 16),
-                &t,
-                &shine_effect_sheet,
-                shine_frame,
-            );
+    if terminal {
+        if replaying {
+            return Err("replays can only be watched in the window".to_string());
         }
-        
-        gfx.draw_next_piece(&t_next, &sprite_sheet);
-        gfx.draw_hold_piece(&t_hold, &sprite_sheet);
-
-        gfx.draw_background(vec![&foreground_surface]);
-        gfx.draw_score(23 * 16 + 3, 10 * 16 - 1, score, &font_sheet);
-        gfx.present();
-
-        let current_ticks: u32 = sdl_timer.ticks();
-        delta_time = current_ticks - last_ticks;
-        shine_frame = 6 - ((place_timer as i32 / 10) % 7);
-        ::std::thread::sleep(Duration::new(
-            0,
-            (1_000_000_000u32 - (delta_time % 1_000) * 1_000_000u32) / 200,
-        ));
+        tui::play(&mut session)?;
+    } else {
+        play_in_window(&mut session, replaying, scale.as_deref(), fullscreen)?;
     }
 
-    Ok(())
+    if replaying {
+        return Ok(());
+    }
+    end_game(
+        session.game,
+        session.recording,
+        high_scores,
+        &high_score_key,
+    )
 }
@@ -0,0 +1,285 @@
+use std::ops::RangeInclusive;
+
+use crate::fumen::{self, FumenPage};
+use crate::game_board::GameBoard;
+use crate::game_mode::{
+    GameMode, DIG_MESSINESS, DIG_ROWS, MARATHON_LINES, MAX_START_LEVEL, SPRINT_LINES, ULTRA_SECONDS,
+};
+use crate::high_scores::{HighScores, HIGH_SCORE_FILE};
+use crate::puzzle::{load_puzzles, Puzzle, PUZZLE_FILE};
+use crate::tetris_game::{StackVisibility, TetrisGame, TICKS_PER_SECOND};
+
+// Rows of the vanish zone above the visible field, as in the guideline.
+const HIDDEN_ROWS: i32 = 20;
+
+// Command line options, e.g. `--mode sprint --lines 40 --width 10 --height 20`.
+pub struct Options {
+    pub width: i32,
+    pub height: i32,
+    pub mode: GameMode,
+    pub seed: u64,
+    pub visibility: StackVisibility,
+    pub puzzle: Option<Puzzle>,
+    pub fumen: Option<FumenPage>, // the board starts out with this page's field
+    pub args: Vec<String>,        // the arguments with the seed and puzzle pinned down, for replays
+}
+
+// The usual board, results on any other are kept apart from it.
+const WIDTH: i32 = 10;
+const HEIGHT: i32 = 20;
+
+// Key a game's best result is kept under: its mode, how its stack is shown and the board
+// when it isn't the usual empty one.
+fn high_score_key(
+    mode: &GameMode,
+    visibility: StackVisibility,
+    (width, height): (i32, i32),
+    fumen: Option<&FumenPage>,
+) -> String {
+    let mut key = format!("{}{}", mode.name(), visibility.name());
+    if (width, height) != (WIDTH, HEIGHT) {
+        key += &format!("-{}x{}", width, height);
+    }
+    if let Some(page) = fumen.filter(|page| !page.board.is_empty()) {
+        key += &format!("-fumen{:016x}", page.board.grid_hash());
+    }
+    key
+}
+
+pub fn parse_number(arg: &str, value: &str, range: RangeInclusive<i32>) -> Result<i32, String> {
+    value
+        .parse()
+        .ok()
+        .filter(|v| range.contains(v))
+        .ok_or(format!(
+            "{} needs a number from {} to {}",
+            arg,
+            range.start(),
+            range.end()
+        ))
+}
+
+// Removes `name value` from the arguments and returns the value, for arguments that
+// aren't game options such as where to write a picture.
+pub fn take_arg(args: &mut Vec<String>, name: &str) -> Result<Option<String>, String> {
+    let Some(i) = args.iter().position(|arg| arg == name) else {
+        return Ok(None);
+    };
+    if i + 1 == args.len() {
+        return Err(format!("{} needs a value", name));
+    }
+    let value = args.remove(i + 1);
+    args.remove(i);
+    Ok(Some(value))
+}
+
+pub fn parse_options(args: &[String]) -> Result<Options, String> {
+    let mut width = WIDTH;
+    let mut height = HEIGHT;
+    let mut mode = "endless".to_string();
+    let mut lines = 40;
+    let mut seconds = ULTRA_SECONDS as i32;
+    let mut start_level = 1;
+    let mut goal = Some(MARATHON_LINES[0]);
+    let mut rows = DIG_ROWS[0];
+    let mut messiness = DIG_MESSINESS as i32;
+    let mut seed: u64 = rand::random();
+    let mut stack = "visible".to_string();
+    let mut fade_seconds = 5;
+    let mut gravity = 0;
+    let mut puzzle_choice = None;
+    let mut fumen = None;
+    let mut arg_values = args.iter();
+    while let Some(arg) = arg_values.next() {
+        let value = arg_values.next().ok_or(format!("{} needs a value", arg))?;
+        match arg.as_str() {
+            "--width" => width = parse_number(arg, value, 4..=20)?,
+            "--height" => height = parse_number(arg, value, 10..=40)?,
+            "--mode" => mode = value.clone(),
+            "--time" => seconds = parse_number(arg, value, 30..=600)?,
+            "--level" => start_level = parse_number(arg, value, 1..=MAX_START_LEVEL as i32)?,
+            "--goal" => {
+                goal = match value.parse() {
+                    Ok(lines) if MARATHON_LINES.contains(&lines) => Some(lines),
+                    _ if value == "endless" => None,
+                    _ => {
+                        return Err(format!(
+                            "--goal needs one of {:?} or endless",
+                            MARATHON_LINES
+                        ))
+                    }
+                }
+            }
+            "--messiness" => messiness = parse_number(arg, value, 0..=100)?,
+            "--gravity" => gravity = parse_number(arg, value, 0..=60)?,
+            "--stack" => stack = value.clone(),
+            "--fade" => fade_seconds = parse_number(arg, value, 1..=30)?,
+            "--puzzle" => puzzle_choice = Some(value.clone()),
+            "--fumen" => fumen = Some(fumen::decode(value)?.remove(0)),
+            "--seed" => seed = value.parse().map_err(|_| "--seed needs a number")?,
+            "--rows" => {
+                rows = value
+                    .parse()
+                    .ok()
+                    .filter(|v| DIG_ROWS.contains(v))
+                    .ok_or(format!("--rows needs one of {:?}", DIG_ROWS))?
+            }
+            "--lines" => {
+                lines = value
+                    .parse()
+                    .ok()
+                    .filter(|v| SPRINT_LINES.contains(v))
+                    .ok_or(format!("--lines needs one of {:?}", SPRINT_LINES))?
+            }
+            _ => return Err(format!("unknown argument {}", arg)),
+        }
+    }
+    let mut args = args.to_vec();
+    args.extend(["--seed".to_string(), seed.to_string()]);
+    let visibility = match stack.as_str() {
+        "visible" => StackVisibility::Visible,
+        "invisible" => StackVisibility::Invisible,
+        "fade" => StackVisibility::Fading {
+            ticks: fade_seconds as u32 * TICKS_PER_SECOND,
+        },
+        "flash" => StackVisibility::Flash,
+        _ => return Err(format!("unknown stack visibility {}", stack)),
+    };
+    let mut puzzle = None;
+    let mode = match mode.as_str() {
+        "endless" => GameMode::Endless,
+        "sprint" => GameMode::Sprint { lines },
+        "ultra" => GameMode::Ultra {
+            seconds: seconds as u32,
+        },
+        "marathon" => GameMode::Marathon {
+            start_level: start_level as u32,
+            lines: goal,
+        },
+        "survival" => GameMode::Survival,
+        // The setups are laid out for the usual width.
+        "pc" if width != WIDTH => return Err(format!("pc needs a board {} wide", WIDTH)),
+        "pc" => GameMode::PerfectClear,
+        "master" => GameMode::Master,
+        "zen" => GameMode::Zen {
+            gravity: gravity as u32,
+        },
+        "dig" => GameMode::Dig {
+            rows,
+            messiness: messiness as u32,
+        },
+        "puzzle" => {
+            let puzzles = load_puzzles(PUZZLE_FILE)?;
+            let puzzle_mode = |index: usize| GameMode::Puzzle {
+                number: index as u32 + 1,
+                id: puzzles[index].id(),
+                goal: puzzles[index].goal,
+                hold: puzzles[index].hold,
+            };
+            // --puzzle takes a puzzle's id or its number in the file. Without it, carry on
+            // with the first one that hasn't been solved yet with this stack visibility.
+            let index = match &puzzle_choice {
+                Some(choice) => match choice.parse::<usize>() {
+                    Ok(number) => number.checked_sub(1).filter(|i| *i < puzzles.len()),
+                    Err(_) => puzzles.iter().position(|p| p.id() == *choice),
+                }
+                .ok_or(format!("there is no puzzle {} in {}", choice, PUZZLE_FILE))?,
+                None => {
+                    let high_scores = HighScores::load(HIGH_SCORE_FILE);
+                    (0..puzzles.len())
+                        .find(|i| {
+                            let key = high_score_key(
+                                &puzzle_mode(*i),
+                                visibility,
+                                (puzzles[*i].width(), height),
+                                fumen.as_ref(),
+                            );
+                            high_scores.best(&key).is_none()
+                        })
+                        .unwrap_or(0)
+                }
+            };
+            let mode = puzzle_mode(index);
+            let chosen = puzzles[index].clone();
+            width = chosen.width();
+            args.extend(["--puzzle".to_string(), chosen.id()]);
+            puzzle = Some(chosen);
+            mode
+        }
+        _ => return Err(format!("unknown mode {}", mode)),
+    };
+    Ok(Options {
+        width,
+        height,
+        mode,
+        seed,
+        visibility,
+        puzzle,
+        fumen,
+        args,
+    })
+}
+
+impl Options {
+    pub fn high_score_key(&self) -> String {
+        high_score_key(
+            &self.mode,
+            self.visibility,
+            (self.width, self.height),
+            self.fumen.as_ref(),
+        )
+    }
+
+    pub fn new_game(&self) -> Result<TetrisGame, String> {
+        let mut board = match &self.puzzle {
+            Some(puzzle) => puzzle.board(self.height, HIDDEN_ROWS),
+            None => GameBoard::with_hidden_rows(self.width, self.height, HIDDEN_ROWS),
+        };
+        if let Some(page) = &self.fumen {
+            page.fill_board(&mut board)?;
+        }
+        let mut game = TetrisGame::new(board, self.mode.clone(), self.seed);
+        game.visibility = self.visibility;
+        if let Some(puzzle) = &self.puzzle {
+            game.set_queue(&puzzle.queue);
+        }
+        Ok(game)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(args: &str) -> String {
+        let args: Vec<String> = args.split_whitespace().map(str::to_string).collect();
+        parse_options(&args).unwrap().high_score_key()
+    }
+
+    #[test]
+    fn test_high_score_key() {
+        assert_eq!(key("--mode sprint --lines 40"), "sprint40");
+        assert_eq!(key("--mode sprint --lines 40 --width 10"), "sprint40");
+        assert_eq!(key("--mode sprint --lines 40 --width 4"), "sprint40-4x20");
+        assert_eq!(
+            key("--mode sprint --lines 40 --stack invisible --height 24"),
+            "sprint40-invisible-10x24"
+        );
+        // An empty field is the usual board, others are told apart by their blocks.
+        assert_eq!(
+            key("--mode sprint --lines 40 --fumen v115@vhAVQn"),
+            "sprint40"
+        );
+        let mut board = GameBoard::with_hidden_rows(10, 20, 20);
+        board.set_cell(0, 39, 8);
+        let one = fumen::fumen_url(&board, None).unwrap();
+        board.set_cell(1, 39, 8);
+        let two = fumen::fumen_url(&board, None).unwrap();
+        let one = key(&format!("--mode sprint --lines 40 --fumen {}", one));
+        assert!(one.starts_with("sprint40-fumen"));
+        assert_ne!(
+            one,
+            key(&format!("--mode sprint --lines 40 --fumen {}", two))
+        );
+    }
+}
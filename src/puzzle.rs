@@ -0,0 +1,293 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use crate::fumen;
+use crate::game_board::{block_id, GARBAGE_ID};
+use crate::tetris_piece::TetrisPieceType;
+use crate::GameBoard;
+
+pub const PUZZLE_FILE: &str = "puzzles.txt";
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum PuzzleGoal {
+    Lines(u32),
+    PerfectClear,
+    TSpinTriple,
+}
+
+impl fmt::Display for PuzzleGoal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PuzzleGoal::Lines(1) => write!(f, "1 LINE"),
+            PuzzleGoal::Lines(lines) => write!(f, "{} LINES", lines),
+            PuzzleGoal::PerfectClear => write!(f, "PERFECT CLEAR"),
+            PuzzleGoal::TSpinTriple => write!(f, "T-SPIN TRIPLE"),
+        }
+    }
+}
+
+// A challenge: reach the goal on a prepared board with a fixed set of pieces.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Puzzle {
+    pub name: String,
+    pub goal: PuzzleGoal,
+    pub queue: Vec<TetrisPieceType>,
+    pub hold: bool,
+    pub rows: Vec<Vec<i32>>, // block ids of the bottom rows of the board, top row first
+}
+
+impl Puzzle {
+    // The name in lower case with dashes between its words, e.g. `t-spin-triple`. Progress
+    // and replays refer to a puzzle by it, so that it doesn't matter where it is in the file.
+    pub fn id(&self) -> String {
+        self.name
+            .to_lowercase()
+            .split(|c: char| !c.is_ascii_alphanumeric())
+            .filter(|word| !word.is_empty())
+            .collect::<Vec<_>>()
+            .join("-")
+    }
+
+    pub fn width(&self) -> i32 {
+        self.rows.first().map_or(10, |row| row.len() as i32)
+    }
+
+    // A board with the puzzle's rows at the bottom of its visible field.
+    pub fn board(&self, visible_height: i32, hidden_rows: i32) -> GameBoard {
+        let visible_height = visible_height.max(self.rows.len() as i32);
+        let mut board = GameBoard::with_hidden_rows(self.width(), visible_height, hidden_rows);
+        let top = board.height - self.rows.len() as i32;
+        for (y, row) in self.rows.iter().enumerate() {
+            for (x, id) in row.iter().enumerate() {
+                board.set_cell(x as i32, top + y as i32, *id);
+            }
+        }
+        board
+    }
+}
+
+pub fn load_puzzles(path: impl AsRef<Path>) -> Result<Vec<Puzzle>, String> {
+    let path = path.as_ref();
+    let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    parse_puzzles(&text)
+}
+
+// Puzzles are blocks of lines separated by blank lines, e.g.
+//
+//     name Tetris
+//     goal lines 4
+//     queue I
+//     hold no
+//     IIIIIIIII.
+//     XXXXXXXXX.
+//
+// The board rows are in the text form of a board, `.` for an empty cell, piece letters
+// and `X` for garbage, and end up at the bottom of the board. Block ids as digits work
+// too. Instead of rows, `fumen v115@...` takes the board from the
+// first page of a fumen, and its pieces make up the queue unless there is a queue line.
+// Lines starting with # are comments.
+pub fn parse_puzzles(text: &str) -> Result<Vec<Puzzle>, String> {
+    let mut puzzles = Vec::new();
+    let mut lines = text
+        .lines()
+        .map(str::trim)
+        .enumerate()
+        .filter(|(_, line)| !line.starts_with('#'))
+        .peekable();
+    while lines.peek().is_some() {
+        let block: Vec<(usize, &str)> = lines
+            .by_ref()
+            .skip_while(|(_, line)| line.is_empty())
+            .take_while(|(_, line)| !line.is_empty())
+            .collect();
+        if !block.is_empty() {
+            let puzzle = parse_puzzle(&block)?;
+            if puzzle.id().is_empty() {
+                return Err(format!(
+                    "puzzle line {}: puzzle needs a name",
+                    block[0].0 + 1
+                ));
+            }
+            if puzzles.iter().any(|p: &Puzzle| p.id() == puzzle.id()) {
+                return Err(format!("there are two puzzles named {}", puzzle.name));
+            }
+            puzzles.push(puzzle);
+        }
+    }
+    Ok(puzzles)
+}
+
+fn parse_puzzle(lines: &[(usize, &str)]) -> Result<Puzzle, String> {
+    let mut puzzle = Puzzle {
+        name: String::new(),
+        goal: PuzzleGoal::Lines(1),
+        queue: Vec::new(),
+        hold: true,
+        rows: Vec::new(),
+    };
+    let mut fumen_queue = Vec::new();
+    for (number, line) in lines {
+        let error = |message: &str| format!("puzzle line {}: {}", number + 1, message);
+        let row: Option<Vec<i32>> = line
+            .chars()
+            .map(|c| c.to_digit(10).map(|id| id as i32).or(block_id(c)))
+            .collect();
+        if let Some(row) = row {
+            if row.iter().any(|id| *id > GARBAGE_ID) {
+                return Err(error("bad block id"));
+            }
+            if !puzzle.rows.is_empty() && row.len() != puzzle.rows[0].len() {
+                return Err(error("board rows differ in width"));
+            }
+            puzzle.rows.push(row);
+            continue;
+        }
+        let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+        match key {
+            "name" => puzzle.name = value.to_string(),
+            "fumen" => {
+                let pages = fumen::decode(value).map_err(|e| error(&e))?;
+                let board = &pages[0].board;
+                let top = (0..board.height)
+                    .find(|y| (0..board.width).any(|x| board.cell(x, *y) != 0))
+                    .unwrap_or(board.height);
+                puzzle.rows = (top..board.height)
+                    .map(|y| (0..board.width).map(|x| board.cell(x, y)).collect())
+                    .collect();
+                fumen_queue = pages
+                    .iter()
+                    .filter_map(|page| Some(page.piece?.piece_type))
+                    .collect();
+            }
+            "goal" => {
+                puzzle.goal = match value.split_whitespace().collect::<Vec<_>>()[..] {
+                    ["lines", lines] => {
+                        PuzzleGoal::Lines(lines.parse().map_err(|_| error("bad line count"))?)
+                    }
+                    ["perfect-clear"] => PuzzleGoal::PerfectClear,
+                    ["tspin-triple"] => PuzzleGoal::TSpinTriple,
+                    _ => return Err(error("unknown goal")),
+                }
+            }
+            "queue" => {
+                puzzle.queue = value
+                    .chars()
+                    .map(TetrisPieceType::from_letter)
+                    .collect::<Option<Vec<_>>>()
+                    .ok_or_else(|| error("queue needs piece letters"))?
+            }
+            "hold" => {
+                puzzle.hold = match value {
+                    "yes" => true,
+                    "no" => false,
+                    _ => return Err(error("hold needs yes or no")),
+                }
+            }
+            _ => return Err(error("unknown key")),
+        }
+    }
+    if puzzle.queue.is_empty() {
+        puzzle.queue = fumen_queue;
+    }
+    if puzzle.queue.is_empty() {
+        return Err(format!("puzzle {} has no pieces", puzzle.name));
+    }
+    if puzzle
+        .rows
+        .first()
+        .is_some_and(|row| row.is_empty() || row.len() > 20)
+    {
+        return Err(format!("puzzle {} has a bad board width", puzzle.name));
+    }
+    Ok(puzzle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fumen::FumenPage;
+    use crate::tetris_piece::TetrisPiece;
+
+    #[test]
+    fn test_parse_puzzles() {
+        let puzzles = parse_puzzles(
+            "# two puzzles\n\nname First\ngoal lines 2\nqueue IT\nhold no\n.II\nI.1\n\n\
+             name Second\ngoal perfect-clear\nqueue o\n",
+        )
+        .unwrap();
+        assert_eq!(puzzles.len(), 2);
+        assert_eq!(puzzles[0].goal, PuzzleGoal::Lines(2));
+        assert_eq!(
+            puzzles[0].queue,
+            vec![TetrisPieceType::I, TetrisPieceType::T]
+        );
+        assert!(!puzzles[0].hold);
+        let board = puzzles[0].board(10, 2);
+        assert_eq!((board.width, board.height), (3, 12));
+        assert_eq!(board.cell(0, 10), 0);
+        assert_eq!(board.cell(1, 11), 0);
+        assert_eq!(board.cell(2, 11), 1);
+        assert_eq!(puzzles[1].goal, PuzzleGoal::PerfectClear);
+        assert!(puzzles[1].hold);
+        assert!(parse_puzzles("name Bad\nqueue X\n").is_err());
+        assert!(parse_puzzles("name Bad\nfumen v115@vh\n").is_err());
+        assert!(parse_puzzles("name Bad\nqueue I\n01\n011\n").is_err());
+        assert!(parse_puzzles("queue I\n").is_err());
+        assert_eq!(
+            parse_puzzles("name Bad\nqueue I\n.9\n"),
+            Err("puzzle line 3: bad block id".to_string())
+        );
+        assert!(parse_puzzles("name Twice\nqueue I\n\nname twice!\nqueue O\n").is_err());
+        assert_eq!(puzzles[0].id(), "first");
+    }
+
+    #[test]
+    fn test_fumen_puzzle() {
+        let mut page = FumenPage {
+            board: GameBoard::new(10, 23),
+            piece: None,
+            lock: false,
+            comment: String::new(),
+        };
+        for x in 0..9 {
+            page.board.set_cell(x, 22, 8);
+        }
+        page.board.set_cell(0, 21, 8);
+        let mut pages = vec![page.clone()];
+        for piece_type in [TetrisPieceType::I, TetrisPieceType::O] {
+            let mut piece = TetrisPiece::new(piece_type);
+            piece.y = 5;
+            pages.push(FumenPage {
+                piece: Some(piece),
+                ..page.clone()
+            });
+        }
+        let text = format!(
+            "name Fumen\nfumen https://fumen.zui.jp/?{}\n",
+            fumen::encode(&pages).unwrap()
+        );
+        let puzzles = parse_puzzles(&text).unwrap();
+        assert_eq!(puzzles[0].rows.len(), 2);
+        assert_eq!(puzzles[0].rows[0][..2], [8, 0]);
+        assert_eq!(puzzles[0].rows[1][9], 0);
+        assert_eq!(
+            puzzles[0].queue,
+            vec![TetrisPieceType::I, TetrisPieceType::O]
+        );
+        let text = format!("{}queue T\n", text);
+        assert_eq!(
+            parse_puzzles(&text).unwrap()[0].queue,
+            vec![TetrisPieceType::T]
+        );
+    }
+
+    #[test]
+    fn test_bundled_puzzles() {
+        let puzzles = parse_puzzles(include_str!("../puzzles.txt")).unwrap();
+        assert!(!puzzles.is_empty());
+        assert!(puzzles.iter().all(|p| p.width() == 10));
+        assert_eq!(puzzles[3].id(), "t-spin-triple");
+    }
+}
@@ -0,0 +1,377 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::tetris_game::{Input, TetrisGame, TICKS_PER_SECOND};
+use crate::tetris_piece::TetrisPieceType;
+
+pub const REPLAY_DIR: &str = "replays";
+const REPLAY_VERSION: u32 = 1;
+
+// The player keeps a copy of the game this often, seeking starts from the closest one.
+const SNAPSHOT_TICKS: u32 = 5 * TICKS_PER_SECOND;
+
+// How long a replay may go on after its last input, as long as the longest ultra. Playback
+// runs up to the recorded end, so a longer one would only keep a verifier busy.
+const MAX_IDLE_TICKS: u32 = 600 * TICKS_PER_SECOND;
+
+// Everything needed to play a game again: the options it was started with, seed included,
+// and every input with the tick it came before. The result is kept to check the playback.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Replay {
+    pub args: Vec<String>,
+    pub inputs: Vec<(u32, Input)>,
+    pub score: i32,
+    pub lines: u32,
+    pub ticks: u32,
+    pub board_hash: Option<u64>, // GameBoard::grid_hash of the final board, older replays lack it
+}
+
+impl Replay {
+    // Where the replay of a game is written, one file per mode and seed.
+    pub fn path(game: &TetrisGame) -> PathBuf {
+        Path::new(REPLAY_DIR).join(format!("{}-{}.txt", game.mode.name(), game.seed))
+    }
+
+    // Applies an input to the game being played and records it, inputs after the game
+    // ended are dropped.
+    pub fn record(&mut self, game: &mut TetrisGame, input: Input) {
+        if game.game_over.is_none() {
+            self.inputs.push((game.ticks, input));
+            game.apply(input);
+        }
+    }
+
+    pub fn finish(&mut self, game: &TetrisGame) {
+        self.score = game.score;
+        self.lines = game.lines;
+        self.ticks = game.ticks;
+        self.board_hash = Some(game.board.grid_hash());
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Replay, String> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        text.parse()
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), String> {
+        let path = path.as_ref();
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        }
+        fs::write(path, self.to_string()).map_err(|e| e.to_string())
+    }
+}
+
+// A replay file starts with its format version, the options and the result, followed by
+// one `tick input` line per input:
+//
+//     version 1
+//     args --mode sprint --lines 40 --seed 12345
+//     result score=1200 lines=40 ticks=18000 board=5d0f2c8e9a61b374
+//     0 shift -1
+//     34 shift 0
+//     35 drop
+impl std::fmt::Display for Replay {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "version {}", REPLAY_VERSION)?;
+        writeln!(f, "args {}", self.args.join(" "))?;
+        write!(
+            f,
+            "result score={} lines={} ticks={}",
+            self.score, self.lines, self.ticks
+        )?;
+        if let Some(hash) = self.board_hash {
+            write!(f, " board={:016x}", hash)?;
+        }
+        writeln!(f)?;
+        for (tick, input) in &self.inputs {
+            writeln!(f, "{} {}", tick, format_input(input))?;
+        }
+        Ok(())
+    }
+}
+
+impl std::str::FromStr for Replay {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Replay, String> {
+        let mut lines = text.lines().enumerate();
+        let mut header = |key: &str| {
+            let (_, line) = lines
+                .next()
+                .ok_or(format!("replay is missing its {}", key))?;
+            line.strip_prefix(key)
+                .map(str::trim)
+                .ok_or(format!("replay is missing its {}", key))
+        };
+        match header("version")?.parse() {
+            Ok(REPLAY_VERSION) => {}
+            _ => return Err(format!("replay needs format version {}", REPLAY_VERSION)),
+        }
+        let mut replay = Replay {
+            args: header("args")?
+                .split_whitespace()
+                .map(str::to_string)
+                .collect(),
+            ..Replay::default()
+        };
+        for field in header("result")?.split_whitespace() {
+            let (key, value) = field.split_once('=').unwrap_or((field, ""));
+            let bad_result = || format!("bad replay result {}", field);
+            match key {
+                "score" => replay.score = value.parse().map_err(|_| bad_result())?,
+                "lines" => replay.lines = value.parse().map_err(|_| bad_result())?,
+                "ticks" => replay.ticks = value.parse().map_err(|_| bad_result())?,
+                "board" => {
+                    replay.board_hash =
+                        Some(u64::from_str_radix(value, 16).map_err(|_| bad_result())?)
+                }
+                _ => {}
+            }
+        }
+        for (number, line) in lines.filter(|(_, line)| !line.trim().is_empty()) {
+            let input = line
+                .split_once(' ')
+                .and_then(|(tick, input)| Some((tick.parse().ok()?, parse_input(input)?)))
+                .ok_or(format!("replay line {}: bad input", number + 1))?;
+            if input.0 > replay.ticks {
+                return Err(format!("replay line {}: input after the end", number + 1));
+            }
+            replay.inputs.push(input);
+        }
+        let last_input = replay.inputs.last().map_or(0, |(tick, _)| *tick);
+        if replay.ticks > last_input.saturating_add(MAX_IDLE_TICKS) {
+            return Err("replay goes on too long after its last input".to_string());
+        }
+        Ok(replay)
+    }
+}
+
+fn format_input(input: &Input) -> String {
+    match input {
+        Input::Shift(direction) => format!("shift {}", direction),
+        Input::SoftDrop => "soft".to_string(),
+        Input::Rotate(true) => "rotate cw".to_string(),
+        Input::Rotate(false) => "rotate ccw".to_string(),
+        Input::Hold => "hold".to_string(),
+        Input::HardDrop => "drop".to_string(),
+        Input::Undo => "undo".to_string(),
+        Input::Redo => "redo".to_string(),
+        Input::ChooseNext(Some(piece_type)) => format!("next {:?}", piece_type),
+        Input::ChooseNext(None) => "next random".to_string(),
+    }
+}
+
+fn parse_input(text: &str) -> Option<Input> {
+    let input = match text.split_whitespace().collect::<Vec<_>>()[..] {
+        ["shift", direction] => {
+            Input::Shift(direction.parse().ok().filter(|d: &i32| d.abs() <= 1)?)
+        }
+        ["soft"] => Input::SoftDrop,
+        ["rotate", "cw"] => Input::Rotate(true),
+        ["rotate", "ccw"] => Input::Rotate(false),
+        ["hold"] => Input::Hold,
+        ["drop"] => Input::HardDrop,
+        ["undo"] => Input::Undo,
+        ["redo"] => Input::Redo,
+        ["next", "random"] => Input::ChooseNext(None),
+        ["next", letter] if letter.len() == 1 => {
+            Input::ChooseNext(Some(TetrisPieceType::from_letter(letter.chars().next()?)?))
+        }
+        _ => return None,
+    };
+    Some(input)
+}
+
+// Feeds a replay's inputs back into a game tick by tick. The game itself stays with the
+// caller, so it is drawn and ticked like one that is being played.
+pub struct ReplayPlayer {
+    pub replay: Replay,
+    pub paused: bool,
+    pub speed: u32, // ticks run per tick of real time
+    next_input: usize,
+    snapshots: Vec<(usize, TetrisGame)>, // index of the next input to apply, game state
+}
+
+impl ReplayPlayer {
+    pub fn new(replay: Replay, game: &TetrisGame) -> ReplayPlayer {
+        ReplayPlayer {
+            replay,
+            paused: false,
+            speed: 1,
+            next_input: 0,
+            snapshots: vec![(0, game.clone())],
+        }
+    }
+
+    // Whether the game has come as far as the recording went.
+    pub fn is_finished(&self, game: &TetrisGame) -> bool {
+        game.game_over.is_some()
+            || (game.ticks >= self.replay.ticks && self.next_input == self.replay.inputs.len())
+    }
+
+    // Applies the inputs recorded before the game's next tick and runs it.
+    pub fn step(&mut self, game: &mut TetrisGame) {
+        while let Some((tick, input)) = self.replay.inputs.get(self.next_input) {
+            if *tick > game.ticks {
+                break;
+            }
+            game.apply(*input);
+            self.next_input += 1;
+        }
+        if game.ticks >= self.replay.ticks {
+            return;
+        }
+        game.tick();
+        let snapshot_tick = self.snapshots.last().map_or(0, |(_, game)| game.ticks);
+        if game.ticks.is_multiple_of(SNAPSHOT_TICKS) && game.ticks > snapshot_tick {
+            self.snapshots.push((self.next_input, game.clone()));
+        }
+    }
+
+    // Jumps to a tick, starting from the latest snapshot before it.
+    pub fn seek(&mut self, game: &mut TetrisGame, tick: u32) {
+        let (next_input, snapshot) = self
+            .snapshots
+            .iter()
+            .rev()
+            .find(|(_, snapshot)| snapshot.ticks <= tick)
+            .unwrap_or(&self.snapshots[0]);
+        self.next_input = *next_input;
+        *game = snapshot.clone();
+        while game.ticks < tick && !self.is_finished(game) {
+            self.step(game);
+        }
+    }
+
+    // Plays the rest of the replay and checks that it ends with the recorded result.
+    pub fn verify(&mut self, game: &mut TetrisGame) -> Result<(), String> {
+        while !self.is_finished(game) {
+            self.step(game);
+        }
+        let replay = &self.replay;
+        let mut mismatches = Vec::new();
+        if game.score != replay.score {
+            mismatches.push(format!("score {} instead of {}", game.score, replay.score));
+        }
+        if game.lines != replay.lines {
+            mismatches.push(format!("{} lines instead of {}", game.lines, replay.lines));
+        }
+        if game.ticks != replay.ticks {
+            mismatches.push(format!("{} ticks instead of {}", game.ticks, replay.ticks));
+        }
+        // Replays from before the board was recorded are checked on the rest.
+        if replay
+            .board_hash
+            .is_some_and(|hash| hash != game.board.grid_hash())
+        {
+            mismatches.push("a different board".to_string());
+        }
+        if !mismatches.is_empty() {
+            return Err(format!("replay ended with {}", mismatches.join(", ")));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game_board::GameBoard;
+    use crate::game_mode::GameMode;
+
+    fn new_game() -> TetrisGame {
+        TetrisGame::new(GameBoard::new(10, 20), GameMode::Endless, 99)
+    }
+
+    // Plays a few pieces with inputs every now and then, recording them.
+    fn record_game() -> (Replay, TetrisGame) {
+        let mut game = new_game();
+        let mut replay = Replay {
+            args: vec!["--seed".to_string(), "99".to_string()],
+            ..Replay::default()
+        };
+        let script = [
+            Input::Shift(-1),
+            Input::Shift(0),
+            Input::Rotate(true),
+            Input::HardDrop,
+            Input::Hold,
+            Input::Shift(1),
+            Input::SoftDrop,
+            Input::Shift(0),
+            Input::HardDrop,
+        ];
+        for (i, input) in script.iter().cycle().take(60).enumerate() {
+            replay.inputs.push((game.ticks, *input));
+            game.apply(*input);
+            for _ in 0..(i as u32 * 7) % 90 {
+                game.tick();
+            }
+        }
+        replay.finish(&game);
+        (replay, game)
+    }
+
+    #[test]
+    fn test_replay_round_trip() {
+        let (replay, _) = record_game();
+        let text = replay.to_string();
+        assert!(text.starts_with("version 1\nargs --seed 99\n"));
+        assert_eq!(text.parse::<Replay>(), Ok(replay));
+        assert!("version 2\nargs\nresult\n".parse::<Replay>().is_err());
+        assert!("version 1\nargs\nresult\n5 jump\n"
+            .parse::<Replay>()
+            .is_err());
+        assert!("version 1\nargs\nresult ticks=10\n20 hold\n"
+            .parse::<Replay>()
+            .is_err());
+        assert!("version 1\nargs\nresult ticks=4000000000\n20 hold\n"
+            .parse::<Replay>()
+            .is_err());
+        assert!("version 1\nargs\nresult ticks=120020\n20 hold\n"
+            .parse::<Replay>()
+            .is_ok());
+    }
+
+    #[test]
+    fn test_playback_matches_recording() {
+        let (replay, recorded) = record_game();
+        let mut game = new_game();
+        let mut player = ReplayPlayer::new(replay.clone(), &game);
+        player.verify(&mut game).unwrap();
+        assert_eq!(game.board.grid(), recorded.board.grid());
+
+        let mut seeked = new_game();
+        let mut player = ReplayPlayer::new(replay.clone(), &seeked);
+        player.seek(&mut seeked, replay.ticks / 2);
+        assert_eq!(seeked.ticks, replay.ticks / 2);
+        player.seek(&mut seeked, 10);
+        player.verify(&mut seeked).unwrap();
+        assert_eq!(seeked.board.grid(), recorded.board.grid());
+
+        // Replays saved before the final board was recorded still check out on the rest.
+        let mut old = replay.to_string();
+        old = old.replace(&format!(" board={:016x}", replay.board_hash.unwrap()), "");
+        let old: Replay = old.parse().unwrap();
+        assert_eq!(old.board_hash, None);
+        let mut game = new_game();
+        ReplayPlayer::new(old, &game).verify(&mut game).unwrap();
+
+        let mut tampered = replay.clone();
+        tampered.board_hash = Some(0);
+        let mut game = new_game();
+        assert!(ReplayPlayer::new(tampered, &game)
+            .verify(&mut game)
+            .is_err());
+
+        let mut tampered = replay.clone();
+        tampered.score += 100;
+        let mut game = new_game();
+        assert!(ReplayPlayer::new(tampered, &game)
+            .verify(&mut game)
+            .is_err());
+    }
+}
@@ -0,0 +1,224 @@
+use crate::tetris_piece::TetrisPieceType;
+use crate::GameBoard;
+use crate::TetrisPiece;
+use serde::{Deserialize, Serialize};
+
+const LINE_SCORES: [i32; 5] = [0, 100, 200, 400, 800];
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ScoringSystem {
+    Classic,   // fixed points per cleared line count
+    Guideline, // T-spins, back-to-back, combos and drop points, multiplied by level
+    Master,    // level plus lines, multiplied by the lines and a growing combo multiplier
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum TSpin {
+    None,
+    Mini,
+    Full,
+}
+
+// What locking a piece achieved, kept around to show it to the player.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ClearAction {
+    pub lines: u32,
+    pub t_spin: TSpin,
+    pub back_to_back: bool,
+    pub combo: u32,
+    pub points: i32,
+}
+
+impl ClearAction {
+    pub fn name(&self) -> String {
+        let lines = ["", " SINGLE", " DOUBLE", " TRIPLE", ""][self.lines.min(4) as usize];
+        match self.t_spin {
+            TSpin::Full => format!("T-SPIN{}", lines),
+            TSpin::Mini => format!("MINI T-SPIN{}", lines),
+            TSpin::None if self.lines >= 4 => "TETRIS".to_string(),
+            TSpin::None => lines.trim().to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GameStats {
+    pub pieces: u32,
+    pub tetrises: u32,
+    pub t_spins: u32,
+    pub max_combo: u32,
+}
+
+// Back-to-back and combo state carried from one lock to the next.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Scoring {
+    pub system: ScoringSystem,
+    pub back_to_back: bool,
+    pub combo: i32, // -1 until a piece clears lines
+    pub combo_multiplier: i32,
+}
+
+impl Scoring {
+    pub fn new(system: ScoringSystem) -> Scoring {
+        Scoring {
+            system,
+            back_to_back: false,
+            combo: -1,
+            combo_multiplier: 1,
+        }
+    }
+
+    // Points for moving a piece down by hand, 1 per row soft dropped and 2 per row hard dropped.
+    pub fn drop_points(&self, rows: i32, hard_drop: bool) -> i32 {
+        match self.system {
+            ScoringSystem::Classic | ScoringSystem::Master => 0,
+            ScoringSystem::Guideline if hard_drop => rows * 2,
+            ScoringSystem::Guideline => rows,
+        }
+    }
+
+    pub fn score_lock(&mut self, lines: u32, t_spin: TSpin, level: u32) -> ClearAction {
+        let level = level as i32;
+        if self.system == ScoringSystem::Classic {
+            return ClearAction {
+                lines,
+                t_spin: TSpin::None,
+                back_to_back: false,
+                combo: 0,
+                points: LINE_SCORES[lines.min(4) as usize],
+            };
+        }
+
+        if self.system == ScoringSystem::Master {
+            let lines = lines as i32;
+            if lines == 0 {
+                self.combo = -1;
+                self.combo_multiplier = 1;
+            } else {
+                self.combo += 1;
+                self.combo_multiplier += 2 * lines - 2;
+            }
+            return ClearAction {
+                lines: lines as u32,
+                t_spin: TSpin::None,
+                back_to_back: false,
+                combo: self.combo.max(0) as u32,
+                points: (level + lines + 3) / 4 * lines * self.combo_multiplier,
+            };
+        }
+
+        let base = match (t_spin, lines) {
+            (TSpin::None, 0) => 0,
+            (TSpin::None, 1) => 100,
+            (TSpin::None, 2) => 300,
+            (TSpin::None, 3) => 500,
+            (TSpin::None, _) => 800,
+            (TSpin::Mini, 0) => 100,
+            (TSpin::Mini, 1) => 200,
+            (TSpin::Mini, _) => 400,
+            (TSpin::Full, 0) => 400,
+            (TSpin::Full, 1) => 800,
+            (TSpin::Full, 2) => 1200,
+            (TSpin::Full, _) => 1600,
+        } * level;
+
+        let difficult = lines >= 4 || (lines > 0 && t_spin != TSpin::None);
+        let back_to_back = difficult && self.back_to_back;
+        let mut points = if back_to_back { base * 3 / 2 } else { base };
+        if lines > 0 {
+            self.back_to_back = difficult;
+            self.combo += 1;
+            points += 50 * self.combo * level;
+        } else {
+            self.combo = -1;
+        }
+        ClearAction {
+            lines,
+            t_spin,
+            back_to_back,
+            combo: self.combo.max(0) as u32,
+            points,
+        }
+    }
+}
+
+// Three-corner T-spin check for a T that was just rotated into place, last_kick is the
+// index of the SRS kick that made the rotation fit.
+pub fn detect_t_spin(board: &GameBoard, piece: &TetrisPiece, last_kick: Option<usize>) -> TSpin {
+    let Some(kick) = last_kick else {
+        return TSpin::None;
+    };
+    if piece.piece_type != TetrisPieceType::T {
+        return TSpin::None;
+    }
+    // Corners around the T's center at (2, 2) of its 5x5 box, front corners first for
+    // every rotation.
+    let corners = match piece.rotation {
+        0 => [(1, 1), (3, 1), (1, 3), (3, 3)],
+        1 => [(3, 1), (3, 3), (1, 1), (1, 3)],
+        2 => [(1, 3), (3, 3), (1, 1), (3, 1)],
+        _ => [(1, 1), (1, 3), (3, 1), (3, 3)],
+    };
+    let occupied = corners.map(|(x, y)| {
+        let (x, y) = (piece.x + x, piece.y + y);
+        x < 0 || y < 0 || x >= board.width || y >= board.height || board.cell(x, y) != 0
+    });
+    if occupied.iter().filter(|o| **o).count() < 3 {
+        TSpin::None
+    } else if (occupied[0] && occupied[1]) || kick == 4 {
+        TSpin::Full
+    } else {
+        TSpin::Mini
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_t_spin() {
+        let mut b = GameBoard::new(10, 5);
+        let mut t = TetrisPiece::new(TetrisPieceType::T);
+        t.x = 0;
+        t.y = 0;
+        t.rotation = 2;
+        b.set_cell(1, 1, 1);
+        b.set_cell(3, 1, 1);
+        assert_eq!(detect_t_spin(&b, &t, Some(0)), TSpin::None);
+        b.set_cell(1, 3, 1);
+        assert_eq!(detect_t_spin(&b, &t, Some(0)), TSpin::Mini);
+        assert_eq!(detect_t_spin(&b, &t, Some(4)), TSpin::Full);
+        assert_eq!(detect_t_spin(&b, &t, None), TSpin::None);
+        b.set_cell(3, 3, 1);
+        assert_eq!(detect_t_spin(&b, &t, Some(0)), TSpin::Full);
+    }
+
+    #[test]
+    fn test_guideline_scoring() {
+        let mut scoring = Scoring::new(ScoringSystem::Guideline);
+        let tsd = scoring.score_lock(2, TSpin::Full, 1);
+        assert_eq!((tsd.points, tsd.back_to_back, tsd.combo), (1200, false, 0));
+        assert_eq!(tsd.name(), "T-SPIN DOUBLE");
+        let tetris = scoring.score_lock(4, TSpin::None, 1);
+        assert_eq!(
+            (tetris.points, tetris.back_to_back, tetris.combo),
+            (1250, true, 1)
+        );
+        let single = scoring.score_lock(1, TSpin::None, 2);
+        assert_eq!((single.points, single.back_to_back), (200 + 200, false));
+        assert_eq!(scoring.score_lock(0, TSpin::None, 1).points, 0);
+        assert_eq!(scoring.combo, -1);
+        assert!(!scoring.back_to_back);
+        assert_eq!(scoring.drop_points(5, true), 10);
+    }
+
+    #[test]
+    fn test_master_scoring() {
+        let mut scoring = Scoring::new(ScoringSystem::Master);
+        assert_eq!(scoring.score_lock(4, TSpin::None, 100).points, 26 * 4 * 7);
+        assert_eq!(scoring.score_lock(1, TSpin::None, 104).points, 27 * 7);
+        assert_eq!(scoring.score_lock(0, TSpin::None, 105).points, 0);
+        assert_eq!(scoring.score_lock(2, TSpin::None, 106).points, 27 * 2 * 3);
+    }
+}
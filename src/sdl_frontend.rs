@@ -0,0 +1,255 @@
+use sdl2::event::Event;
+use sdl2::image::LoadSurface;
+use sdl2::keyboard::Keycode;
+use sdl2::render::{Canvas, TextureCreator};
+use sdl2::surface::Surface;
+use sdl2::video::{Window, WindowContext};
+use sdl2::{EventPump, VideoSubsystem};
+use std::str::FromStr;
+
+use crate::frontend::{
+    key_command, key_input, shift_direction, Command, GameView, InputSource, Key, Renderer,
+};
+use crate::tetris_game::{Input, TetrisGame};
+use crate::tetris_game_graphics::{
+    Layout, SpriteSheet, TetrisGameGraphics, BLOCK_SHEET, FONT_SHEET, SHINE_SHEET, SYMBOL_SHEET,
+};
+use crate::tetris_piece::TetrisPieceState;
+
+// How the game is scaled up to the size of the window. Either way its shape is kept and
+// the space left over is filled with black bars.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ScaleMode {
+    Integer, // whole multiples only, so that every pixel of the sprites stays square
+    Fit,     // as large as fits, some pixels end up a little wider than others
+}
+
+impl FromStr for ScaleMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<ScaleMode, String> {
+        match s {
+            "integer" => Ok(ScaleMode::Integer),
+            "fit" => Ok(ScaleMode::Fit),
+            _ => Err(format!("unknown scale mode {}, use integer or fit", s)),
+        }
+    }
+}
+
+// The largest whole scale at which the window fits on the display with some room to
+// spare for its borders.
+fn window_scale(layout: &Layout, display_width: i32, display_height: i32) -> u32 {
+    let scale = ((display_width - 1) / layout.width).min((display_height - 1) / layout.height);
+    scale.max(1) as u32
+}
+
+// Opens a resizable window for a layout, or a fullscreen one, that draws in the layout's
+// logical pixels.
+pub fn open_window(
+    video: &VideoSubsystem,
+    layout: &Layout,
+    scale_mode: ScaleMode,
+    fullscreen: bool,
+) -> Result<Canvas<Window>, String> {
+    let display_mode = video.current_display_mode(0)?;
+    let scale = window_scale(layout, display_mode.w, display_mode.h);
+    let mut builder = video.window(
+        "Clonetris",
+        layout.width as u32 * scale,
+        layout.height as u32 * scale,
+    );
+    builder.position_centered().resizable();
+    if fullscreen {
+        builder.fullscreen_desktop();
+    }
+    let mut window = builder.build().map_err(|e| e.to_string())?;
+    window
+        .set_minimum_size(layout.width as u32, layout.height as u32)
+        .map_err(|e| e.to_string())?;
+
+    // Sprites are scaled by repeating pixels, smoothing them would blur them.
+    sdl2::hint::set("SDL_RENDER_SCALE_QUALITY", "nearest");
+    let mut canvas = window.into_canvas().build().map_err(|e| e.to_string())?;
+    canvas
+        .set_logical_size(layout.width as u32, layout.height as u32)
+        .map_err(|e| e.to_string())?;
+    canvas
+        .set_integer_scale(scale_mode == ScaleMode::Integer)
+        .map_err(|e| e.to_string())?;
+    Ok(canvas)
+}
+
+// Draws into the window with the sprites from the png files.
+pub struct SdlRenderer<'a> {
+    gfx: TetrisGameGraphics<'a>,
+    sprite_sheet: SpriteSheet<'a>,
+    font_sheet: SpriteSheet<'a>,
+    shine_effect_sheet: SpriteSheet<'a>,
+    background: Surface<'static>,
+    foreground: Surface<'static>,
+}
+
+impl<'a> SdlRenderer<'a> {
+    pub fn new(
+        canvas: &'a mut Canvas<Window>,
+        texture_creator: &'a TextureCreator<WindowContext>,
+        layout: Layout,
+    ) -> Result<SdlRenderer<'a>, String> {
+        let spritesheet_surface = Surface::from_file("base_gfx.png")?;
+        let sprite_sheet = SpriteSheet::new_from_surface_with_rect(
+            texture_creator,
+            &spritesheet_surface,
+            16,
+            16,
+            BLOCK_SHEET.into(),
+        );
+        let mut font_sheet = SpriteSheet::new_from_surface_with_rect(
+            texture_creator,
+            &spritesheet_surface,
+            6,
+            6,
+            FONT_SHEET.into(),
+        );
+        let symbol_sheet = SpriteSheet::new_from_surface_with_rect(
+            texture_creator,
+            &spritesheet_surface,
+            6,
+            6,
+            SYMBOL_SHEET.into(),
+        );
+        font_sheet.sprites.extend(symbol_sheet.sprites);
+        let shine_effect_sheet = SpriteSheet::new_from_surface_with_rect(
+            texture_creator,
+            &spritesheet_surface,
+            16,
+            16,
+            SHINE_SHEET.into(),
+        );
+        Ok(SdlRenderer {
+            gfx: TetrisGameGraphics::new(canvas, layout),
+            sprite_sheet,
+            font_sheet,
+            shine_effect_sheet,
+            background: Surface::from_file("background_gfx.png")?,
+            foreground: Surface::from_file("foreground_gfx.png")?,
+        })
+    }
+}
+
+impl Renderer for SdlRenderer<'_> {
+    fn render(&mut self, view: &GameView) -> Result<(), String> {
+        let game = view.game;
+        let gfx = &mut self.gfx;
+        gfx.draw_background(vec![&self.background]);
+        gfx.draw_game_board(&game.board, &mut self.sprite_sheet, |x, y| {
+            game.block_alpha(x, y)
+        });
+        if !game.is_spawning() && game.game_over.is_none() {
+            gfx.draw_board_piece(&game.piece, &self.sprite_sheet);
+        }
+        if game.piece.state == TetrisPieceState::Placing {
+            let shine_frame = 6 - ((game.place_timer() as i32 / 10) % 7);
+            gfx.draw_shine_effect(&game.piece, &self.shine_effect_sheet, shine_frame);
+        }
+
+        gfx.draw_next_piece(&game.next, &self.sprite_sheet);
+        gfx.draw_hold_piece(&game.hold, &self.sprite_sheet);
+
+        gfx.draw_frames(&self.foreground);
+        gfx.draw_score(game.score, &self.font_sheet);
+        if let Some(progress) = game.garbage_progress() {
+            gfx.draw_garbage_meter(progress);
+        }
+        gfx.draw_stats(view.stats, &self.font_sheet);
+        gfx.present();
+        Ok(())
+    }
+}
+
+// Keyboard input from the window's events.
+pub struct SdlInput {
+    event_pump: EventPump,
+    replaying: bool, // the keys control replay playback instead of the game
+}
+
+impl SdlInput {
+    pub fn new(event_pump: EventPump, replaying: bool) -> SdlInput {
+        SdlInput {
+            event_pump,
+            replaying,
+        }
+    }
+}
+
+impl InputSource for SdlInput {
+    fn poll(&mut self, game: &TetrisGame) -> Result<Vec<Command>, String> {
+        let mut commands = Vec::new();
+        for event in self.event_pump.poll_iter() {
+            let command = match event {
+                Event::Quit { .. } => Some(Command::Quit),
+                Event::KeyDown {
+                    keycode: Some(keycode),
+                    repeat,
+                    ..
+                } => key(keycode).and_then(|key| key_down(key, repeat, self.replaying, game)),
+                // Letting go of the direction the piece shifts in stops it.
+                Event::KeyUp {
+                    keycode: Some(keycode),
+                    ..
+                } if !self.replaying => key(keycode)
+                    .and_then(shift_direction)
+                    .filter(|direction| game.shift() == *direction)
+                    .map(|_| Command::Input(Input::Shift(0))),
+                _ => None,
+            };
+            commands.extend(command);
+        }
+        Ok(commands)
+    }
+}
+
+fn key_down(key: Key, repeat: bool, replaying: bool, game: &TetrisGame) -> Option<Command> {
+    if let Some(command) = key_command(key, replaying) {
+        return Some(command);
+    }
+    if replaying {
+        return None;
+    }
+    let input = match shift_direction(key) {
+        // Auto shift is timed by the engine, so key repeats are ignored.
+        Some(direction) => (!repeat).then_some(Input::Shift(direction))?,
+        None => key_input(key, game)?,
+    };
+    Some(Command::Input(input))
+}
+
+// The frontend's name for a key. SDL numbers the keys that type something by their
+// character.
+fn key(keycode: Keycode) -> Option<Key> {
+    let key = match keycode {
+        Keycode::Left => Key::Left,
+        Keycode::Right => Key::Right,
+        Keycode::Down => Key::Down,
+        Keycode::Escape => Key::Escape,
+        Keycode::F12 => Key::F12,
+        _ => Key::Char(char::from_u32(keycode as u32).filter(|c| c.is_ascii() && !c.is_control())?),
+    };
+    Some(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game_board::GameBoard;
+
+    #[test]
+    fn test_window_scale() {
+        let layout = Layout::new(&GameBoard::with_hidden_rows(10, 20, 20));
+        assert_eq!(window_scale(&layout, 1920, 1080), 2);
+        assert_eq!(window_scale(&layout, 3840, 2160), 5);
+        assert_eq!(window_scale(&layout, 1024, 400), 1);
+        assert_eq!(window_scale(&layout, 800, 600), 1);
+        assert_eq!("fit".parse(), Ok(ScaleMode::Fit));
+        assert!("smooth".parse::<ScaleMode>().is_err());
+    }
+}
@@ -0,0 +1,279 @@
+use sdl2::image::{LoadSurface, SaveSurface};
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::rect::Rect;
+use sdl2::render::BlendMode;
+use sdl2::surface::Surface;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::game_board::GARBAGE_ID;
+use crate::tetris_game::TetrisGame;
+use crate::tetris_game_graphics::{
+    font_dots, font_index, font_rect, preview_offset, sprite_rect, Layout, BLOCK_SHEET, COLOR_KEY,
+};
+use crate::tetris_piece::TetrisPiece;
+
+pub const SNAPSHOT_DIR: &str = "snapshots";
+
+// Draws a game into a surface without a window, laid out like the window at its
+// unscaled size, so that a game state can be saved as a picture. Blocks and text are
+// cut from base_gfx.png with the same sprite layout as the window's sprite sheets.
+pub struct SoftwareRenderer {
+    sprites: Surface<'static>,
+    background: Surface<'static>,
+    foreground: Surface<'static>,
+}
+
+impl SoftwareRenderer {
+    pub fn load() -> Result<SoftwareRenderer, String> {
+        let load = |path: &str| Surface::from_file(path).map_err(|e| format!("{}: {}", path, e));
+        let mut sprites = load("base_gfx.png")?;
+        sprites.set_color_key(true, COLOR_KEY)?;
+        sprites.set_blend_mode(BlendMode::Blend)?;
+        Ok(SoftwareRenderer {
+            sprites,
+            background: load("background_gfx.png")?,
+            foreground: load("foreground_gfx.png")?,
+        })
+    }
+
+    // Where a snapshot of a game is written, one file per mode, seed and tick.
+    pub fn snapshot_path(game: &TetrisGame) -> PathBuf {
+        Path::new(SNAPSHOT_DIR).join(format!(
+            "{}-{}-{}.png",
+            game.mode.name(),
+            game.seed,
+            game.ticks
+        ))
+    }
+
+    // Draws the board, the active piece, hold, next, the score and the stats lines the
+    // way the window does, leaving out the shine effect of a locking piece.
+    pub fn render(
+        &mut self,
+        game: &TetrisGame,
+        stats: &[String],
+    ) -> Result<Surface<'static>, String> {
+        let layout = Layout::new(&game.board);
+        let mut surface = Surface::new(
+            layout.width as u32,
+            layout.height as u32,
+            PixelFormatEnum::RGB24,
+        )?;
+        self.background.blit_scaled(None, &mut surface, None)?;
+        self.draw_board(&mut surface, game, &layout)?;
+        if !game.is_spawning() && game.game_over.is_none() {
+            surface.set_clip_rect(Rect::new(
+                layout.board_x,
+                layout.board_y,
+                layout.board_width as u32,
+                layout.board_height as u32,
+            ));
+            self.draw_piece(
+                &mut surface,
+                layout.board_x + game.piece.x * 16,
+                layout.board_y + (game.piece.y - layout.hidden_rows) * 16,
+                &game.piece,
+            )?;
+            surface.set_clip_rect(None);
+        }
+        for (piece, x) in [(&game.next, layout.next_x), (&game.hold, layout.hold_x)] {
+            let (fudge_x, fudge_y) = preview_offset(piece.piece_type);
+            self.draw_piece(
+                &mut surface,
+                x + 16 - fudge_x,
+                layout.panel_y + 16 - fudge_y,
+                piece,
+            )?;
+        }
+        for (src_rect, dest_rect) in layout.frame_pieces() {
+            self.foreground
+                .blit_scaled(src_rect, &mut surface, dest_rect)?;
+        }
+        let (score_x, score_y) = layout.score_text();
+        self.draw_text(
+            &mut surface,
+            score_x,
+            score_y,
+            &format!("{:010}", game.score),
+        )?;
+        if let Some(progress) = game.garbage_progress() {
+            let (meter, color) = layout.garbage_meter(progress);
+            surface.fill_rect(meter, color)?;
+        }
+        if !stats.is_empty() {
+            let area = layout.stats_backdrop(stats.len());
+            let (x, y) = (area.x(), area.y());
+            let mut backdrop = Surface::new(area.width(), area.height(), PixelFormatEnum::RGB24)?;
+            backdrop.fill_rect(None, Color::RGB(0, 0, 0))?;
+            backdrop.set_blend_mode(BlendMode::Blend)?;
+            backdrop.set_alpha_mod(160);
+            backdrop.blit(None, &mut surface, area)?;
+            for (i, line) in stats.iter().enumerate() {
+                self.draw_text(&mut surface, x + 4, y + 4 + i as i32 * 10, line)?;
+            }
+        }
+        Ok(surface)
+    }
+
+    pub fn save_png(
+        &mut self,
+        game: &TetrisGame,
+        stats: &[String],
+        path: impl AsRef<Path>,
+    ) -> Result<(), String> {
+        let path = path.as_ref();
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        }
+        self.render(game, stats)?.save(path)
+    }
+
+    // Draws the visible rows of the board with the opacity the game gives each block.
+    fn draw_board(
+        &mut self,
+        surface: &mut Surface,
+        game: &TetrisGame,
+        layout: &Layout,
+    ) -> Result<(), String> {
+        // The sprite sheet has no garbage block, it is a plain gray one.
+        let mut garbage = Surface::new(16, 16, PixelFormatEnum::RGB24)?;
+        garbage.fill_rect(None, Color::RGB(128, 128, 128))?;
+        garbage.fill_rect(Rect::new(1, 1, 14, 14), Color::RGB(96, 96, 96))?;
+        garbage.set_blend_mode(BlendMode::Blend)?;
+        let board = &game.board;
+        for y in board.hidden_rows..board.height {
+            for x in 0..board.width {
+                let piece_id = board.cell(x, y);
+                let alpha = if piece_id > 0 {
+                    game.block_alpha(x, y)
+                } else {
+                    0
+                };
+                if alpha == 0 {
+                    continue;
+                }
+                let dest_rect = Rect::new(
+                    x * 16 + layout.board_x,
+                    (y - board.hidden_rows) * 16 + layout.board_y,
+                    16,
+                    16,
+                );
+                if piece_id == GARBAGE_ID {
+                    garbage.set_alpha_mod(alpha);
+                    garbage.blit(None, surface, dest_rect)?;
+                } else {
+                    let src_rect = sprite_rect(BLOCK_SHEET, 16, 16, (piece_id - 1) as u32);
+                    self.sprites.set_alpha_mod(alpha);
+                    self.sprites.blit(src_rect, surface, dest_rect)?;
+                    self.sprites.set_alpha_mod(255);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn draw_piece(
+        &self,
+        surface: &mut Surface,
+        x_offset: i32,
+        y_offset: i32,
+        piece: &TetrisPiece,
+    ) -> Result<(), String> {
+        let piece_id = piece.piece_type.id();
+        for (x, y) in piece.cells() {
+            let src_rect = sprite_rect(BLOCK_SHEET, 16, 16, (piece_id - 1) as u32);
+            let dest_rect = Rect::new(x * 16 + x_offset, y * 16 + y_offset, 16, 16);
+            self.sprites.blit(src_rect, surface, dest_rect)?;
+        }
+        Ok(())
+    }
+
+    fn draw_text(
+        &self,
+        surface: &mut Surface,
+        x_offset: i32,
+        y_offset: i32,
+        text: &str,
+    ) -> Result<(), String> {
+        for (i, c) in text.chars().enumerate() {
+            let x = x_offset + i as i32 * 6;
+            for dot_y in font_dots(c) {
+                surface.fill_rect(
+                    Rect::new(x + 2, y_offset + dot_y, 1, 1),
+                    Color::RGB(255, 255, 255),
+                )?;
+            }
+            if let Some(index) = font_index(c) {
+                self.sprites
+                    .blit(font_rect(index), surface, Rect::new(x, y_offset, 6, 6))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game_board::GameBoard;
+    use crate::game_mode::GameMode;
+    use crate::tetris_game_graphics::FRAME_BORDER;
+    use crate::tetris_piece::TetrisPieceType;
+    use sdl2::image::InitFlag;
+
+    // The middle of the I block in base_gfx.png.
+    const I_COLOR: (u8, u8, u8) = (37, 155, 36);
+
+    fn pixel(surface: &Surface, (x, y): (i32, i32)) -> (u8, u8, u8) {
+        let pitch = surface.pitch() as usize;
+        surface.with_lock(|pixels| {
+            let i = y as usize * pitch + x as usize * 3;
+            (pixels[i], pixels[i + 1], pixels[i + 2])
+        })
+    }
+
+    // Middle of a cell in the picture, rows counted from the top of the hidden rows.
+    fn cell_middle(layout: &Layout, x: i32, y: i32) -> (i32, i32) {
+        (
+            layout.board_x + x * 16 + 8,
+            layout.board_y + (y - layout.hidden_rows) * 16 + 8,
+        )
+    }
+
+    #[test]
+    fn test_render_board() {
+        let _image_context = sdl2::image::init(InitFlag::PNG).unwrap();
+        let mut renderer = SoftwareRenderer::load().unwrap();
+        let mut game =
+            TetrisGame::new(GameBoard::with_hidden_rows(10, 20, 2), GameMode::Endless, 0);
+        game.board.set_cell(0, 21, TetrisPieceType::I.id());
+        // An upright I in column 4 from the top row, half of it in the vanish zone.
+        game.piece.piece_type = TetrisPieceType::I;
+        game.piece.rotation = 1;
+        let left = game.piece.cells().map(|(x, _)| x).min().unwrap();
+        let top = game.piece.cells().map(|(_, y)| y).min().unwrap();
+        game.piece.x = 4 - left;
+        game.piece.y = -top;
+        let layout = Layout::new(&game.board);
+        let picture = renderer.render(&game, &[]).unwrap();
+
+        // The same picture with an empty board and the piece out of the way.
+        game.board.set_cell(0, 21, 0);
+        game.piece.x += 4;
+        let empty = renderer.render(&game, &[]).unwrap();
+
+        let block = cell_middle(&layout, 0, 21);
+        assert_eq!(pixel(&picture, block), I_COLOR);
+        assert_ne!(pixel(&empty, block), I_COLOR);
+        let next_to_block = cell_middle(&layout, 1, 21);
+        assert_eq!(pixel(&picture, next_to_block), pixel(&empty, next_to_block));
+
+        assert_eq!(pixel(&picture, cell_middle(&layout, 4, 2)), I_COLOR);
+        assert_eq!(pixel(&picture, cell_middle(&layout, 4, 3)), I_COLOR);
+        // The top row of the vanish zone is above the frame, the piece is cut off there.
+        let vanish_zone = cell_middle(&layout, 4, 0);
+        assert!(vanish_zone.1 < layout.board_y - FRAME_BORDER);
+        assert_eq!(pixel(&picture, vanish_zone), pixel(&empty, vanish_zone));
+    }
+}
@@ -0,0 +1,1088 @@
+use core::fmt;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::game_board::block_id;
+use crate::game_mode::{GameMode, DIG_VISIBLE_ROWS, PC_SETUPS};
+use crate::rng::GameRng;
+use crate::scoring::{detect_t_spin, ClearAction, GameStats, Scoring, TSpin};
+use crate::tetris_piece::TetrisPieceType;
+use crate::timing::Timing;
+use crate::GameBoard;
+use crate::{TetrisPiece, TetrisPieceState};
+
+// The engine advances in fixed steps, all game timers count these ticks.
+pub const TICKS_PER_SECOND: u32 = 200;
+pub const TICK_MS: u32 = 1000 / TICKS_PER_SECOND;
+
+// Gravity is measured in fractions of a row per tick.
+pub const GRAVITY_ONE_ROW: u32 = 65536;
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum GameOverReason {
+    BlockOut,    // a new piece overlaps the stack where it spawns
+    LockOut,     // a piece locked entirely inside the vanish zone
+    GarbageOut,  // garbage pushed blocks past the top of the vanish zone
+    GoalReached, // the mode's goal was completed
+    TimeUp,      // the mode's time limit ran out
+    OutOfPieces, // a fixed piece queue ran out before the goal was reached
+}
+
+impl fmt::Display for GameOverReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GameOverReason::BlockOut => write!(f, "block out"),
+            GameOverReason::LockOut => write!(f, "lock out"),
+            GameOverReason::GarbageOut => write!(f, "garbage out"),
+            GameOverReason::GoalReached => write!(f, "goal reached"),
+            GameOverReason::TimeUp => write!(f, "time up"),
+            GameOverReason::OutOfPieces => write!(f, "out of pieces"),
+        }
+    }
+}
+
+// Which top-out conditions end the game, modes can switch them off individually.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TopOutRules {
+    pub block_out: bool,
+    pub lock_out: bool,
+    pub garbage_out: bool,
+    pub clear_board: bool, // an enabled top out empties the board instead of ending the game
+}
+
+impl Default for TopOutRules {
+    fn default() -> TopOutRules {
+        TopOutRules {
+            block_out: true,
+            lock_out: true,
+            garbage_out: true,
+            clear_board: false,
+        }
+    }
+}
+
+// A player action. Actions take effect between two ticks, replays store them together
+// with the tick they came before.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Input {
+    Shift(i32), // the held direction changed, 0 when it was let go
+    SoftDrop,
+    Rotate(bool), // clockwise or not
+    Hold,
+    HardDrop,
+    Undo,
+    Redo,
+    ChooseNext(Option<TetrisPieceType>),
+}
+
+// Results of perfect clear training, an attempt ends with a perfect clear or a miss.
+#[derive(Debug, Copy, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct PerfectClearStats {
+    pub attempts: u32,
+    pub successes: u32,
+    pub streak: u32,
+    pub best_streak: u32,
+}
+
+impl PerfectClearStats {
+    pub fn record(&mut self, success: bool) {
+        self.attempts += 1;
+        if success {
+            self.successes += 1;
+            self.streak += 1;
+            self.best_streak = self.best_streak.max(self.streak);
+        } else {
+            self.streak = 0;
+        }
+    }
+
+    // Share of attempts that ended in a perfect clear, in percent.
+    pub fn success_rate(&self) -> u32 {
+        (self.successes * 100)
+            .checked_div(self.attempts)
+            .unwrap_or(0)
+    }
+}
+
+// How locked blocks are shown, the challenge variants hide the stack from the player.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum StackVisibility {
+    Visible,
+    Invisible,             // blocks disappear as soon as they lock
+    Fading { ticks: u32 }, // blocks start to fade out this long after they locked
+    Flash,                 // invisible, except that the stack flashes up when lines clear
+}
+
+// How long fading blocks take to disappear and how long the stack flashes up for.
+const FADE_TICKS: u32 = TICKS_PER_SECOND / 2;
+const FLASH_MS: u32 = 250;
+
+impl StackVisibility {
+    // Suffix for the high score key, results with a hidden stack are kept apart.
+    pub fn name(&self) -> String {
+        match self {
+            StackVisibility::Visible => String::new(),
+            StackVisibility::Invisible => "-invisible".to_string(),
+            StackVisibility::Fading { ticks } => format!("-fade{}", ticks / TICKS_PER_SECOND),
+            StackVisibility::Flash => "-flash".to_string(),
+        }
+    }
+}
+
+// Everything a placement changes, saved when a piece spawns so that it can be undone.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Snapshot {
+    board: GameBoard,
+    piece: TetrisPiece,
+    next: TetrisPiece,
+    hold: TetrisPiece,
+    score: i32,
+    lines: u32,
+    line_times: Vec<u32>,
+    level: u32,
+    scoring: Scoring,
+    stats: GameStats,
+    last_clear: Option<ClearAction>,
+    rng: GameRng,
+    queue: Option<Vec<TetrisPieceType>>,
+}
+
+// The rules of a running game, independent of how it is drawn or controlled.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TetrisGame {
+    pub board: GameBoard,
+    pub piece: TetrisPiece,
+    pub next: TetrisPiece,
+    pub hold: TetrisPiece,
+    pub score: i32,
+    pub lines: u32,
+    pub line_times: Vec<u32>, // elapsed ms at which each line was cleared
+    pub ticks: u32,
+    pub level: u32,
+    pub section_times: Vec<u32>, // elapsed ms at which each section of 100 levels ended
+    pub scoring: Scoring,
+    pub stats: GameStats,
+    pub last_clear: Option<ClearAction>,
+    pub mode: GameMode,
+    pub seed: u64,
+    pub garbage_cleared: u32, // garbage rows dug out
+    pub garbage_queued: u32,  // garbage rows still to come in from below
+    pub garbage_risen: u32,   // garbage rows pushed up on the mode's timer
+    pub perfect_clears: PerfectClearStats,
+    pub attempt_pieces: u32, // pieces locked in the current perfect clear attempt
+    pub top_out_rules: TopOutRules,
+    pub visibility: StackVisibility,
+    pub game_over: Option<GameOverReason>,
+    gravity_progress: u32,
+    place_timer: u32,
+    spawn_timer: u32, // ticks until the next piece spawns after a lock
+    shift: i32,       // held direction, -1 left, 1 right or 0
+    shift_timer: u32, // ticks the direction has been held for
+    garbage_timer: u32,
+    last_kick: Option<usize>, // set while the last successful move was a rotation
+    rng: GameRng,
+    queue: Option<Vec<TetrisPieceType>>, // fixed pieces to deal instead of random ones
+    bag: Vec<TetrisPieceType>,           // rest of the current 7-bag in modes that deal from one
+    chosen_next: Option<TetrisPieceType>, // deals this piece over and over instead of random ones
+    spawned: Option<Snapshot>,           // state when the active piece spawned, if undo is on
+    undo: Vec<Snapshot>,
+    redo: Vec<Snapshot>,
+}
+
+impl TetrisGame {
+    // Everything random in a game comes from the seed, the same seed replays the same
+    // pieces and garbage.
+    pub fn new(mut board: GameBoard, mode: GameMode, seed: u64) -> TetrisGame {
+        let mut rng = GameRng::new(seed);
+        board.clear_gravity = mode.clear_gravity();
+        let garbage_rows = mode.garbage_rows();
+        let shown = garbage_rows
+            .min(DIG_VISIBLE_ROWS)
+            .min(board.visible_height() as u32 / 2);
+        board.insert_garbage_rows(shown as i32, mode.messiness(), &mut rng);
+        let mut piece = TetrisPiece::new_random_piece(&mut rng);
+        piece.spawn(&board);
+        let next = TetrisPiece::new_random_piece(&mut rng);
+        let mut game = TetrisGame {
+            board,
+            piece,
+            next,
+            hold: TetrisPiece::new(TetrisPieceType::None),
+            score: 0,
+            lines: 0,
+            line_times: Vec::new(),
+            ticks: 0,
+            level: mode.start_level(),
+            section_times: Vec::new(),
+            scoring: Scoring::new(mode.scoring_system()),
+            stats: GameStats::default(),
+            last_clear: None,
+            seed,
+            garbage_cleared: 0,
+            garbage_queued: garbage_rows - shown,
+            garbage_risen: 0,
+            perfect_clears: PerfectClearStats::default(),
+            attempt_pieces: 0,
+            top_out_rules: mode.top_out_rules(),
+            visibility: StackVisibility::Visible,
+            game_over: None,
+            gravity_progress: 0,
+            place_timer: mode.timing(mode.start_level()).lock_delay,
+            spawn_timer: 0,
+            shift: 0,
+            shift_timer: 0,
+            garbage_timer: 0,
+            last_kick: None,
+            rng,
+            queue: None,
+            bag: Vec::new(),
+            chosen_next: None,
+            spawned: None,
+            undo: Vec::new(),
+            redo: Vec::new(),
+            mode,
+        };
+        if game.mode.perfect_clear_attempt().is_some() {
+            game.lay_out_perfect_clear_setup();
+        }
+        if game.mode.uses_bag() {
+            game.piece = game.next_piece();
+            game.piece.spawn(&game.board);
+            game.next = game.next_piece();
+        }
+        if game.mode.allows_undo() {
+            game.spawned = Some(game.snapshot());
+        }
+        game
+    }
+
+    pub fn elapsed_ms(&self) -> u32 {
+        self.ticks * TICK_MS
+    }
+
+    pub fn place_timer(&self) -> u32 {
+        self.place_timer
+    }
+
+    // Opacity of the locked block in a cell from 0 to 255. The whole stack is revealed once
+    // the game is over.
+    pub fn block_alpha(&self, x: i32, y: i32) -> u8 {
+        if self.game_over.is_some() {
+            return 255;
+        }
+        match self.visibility {
+            StackVisibility::Visible => 255,
+            StackVisibility::Invisible => 0,
+            StackVisibility::Fading { ticks } => {
+                let age = self.ticks.saturating_sub(self.board.lock_tick(x, y));
+                let faded = age.saturating_sub(ticks).min(FADE_TICKS);
+                (255 * (FADE_TICKS - faded) / FADE_TICKS) as u8
+            }
+            StackVisibility::Flash => {
+                let flashing = self
+                    .line_times
+                    .last()
+                    .is_some_and(|t| self.elapsed_ms() - t < FLASH_MS);
+                if flashing {
+                    255
+                } else {
+                    0
+                }
+            }
+        }
+    }
+
+    // Deals exactly these pieces, starting with the active one. Once they have all been
+    // placed the game is over.
+    pub fn set_queue(&mut self, queue: &[TetrisPieceType]) {
+        self.queue = Some(queue.to_vec());
+        self.piece = self.next_piece();
+        self.piece.spawn(&self.board);
+        self.next = self.next_piece();
+        if self.mode.allows_undo() {
+            self.spawned = Some(self.snapshot());
+        }
+    }
+
+    // Pieces still to come from a fixed queue, including the next piece.
+    pub fn pieces_left(&self) -> Option<usize> {
+        let queue = self.queue.as_ref()?;
+        Some(queue.len() + usize::from(self.next.piece_type != TetrisPieceType::None))
+    }
+
+    // Deals `piece_type` as every next piece from now on, None goes back to random pieces.
+    pub fn choose_next(&mut self, piece_type: Option<TetrisPieceType>) {
+        self.chosen_next = piece_type;
+        if let Some(piece_type) = piece_type {
+            self.next = TetrisPiece::new(piece_type);
+        }
+    }
+
+    pub fn chosen_next(&self) -> Option<TetrisPieceType> {
+        self.chosen_next
+    }
+
+    pub fn undo_count(&self) -> usize {
+        self.undo.len()
+    }
+
+    // Takes back the last placement, the piece is back where it spawned.
+    pub fn undo(&mut self) -> bool {
+        let Some(snapshot) = self.undo.pop() else {
+            return false;
+        };
+        if let Some(current) = self.spawned.take() {
+            self.redo.push(current);
+        }
+        self.restore(snapshot);
+        true
+    }
+
+    pub fn redo(&mut self) -> bool {
+        let Some(snapshot) = self.redo.pop() else {
+            return false;
+        };
+        if let Some(current) = self.spawned.take() {
+            self.undo.push(current);
+        }
+        self.restore(snapshot);
+        true
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            board: self.board.clone(),
+            piece: self.piece,
+            next: self.next,
+            hold: self.hold,
+            score: self.score,
+            lines: self.lines,
+            line_times: self.line_times.clone(),
+            level: self.level,
+            scoring: self.scoring,
+            stats: self.stats,
+            last_clear: self.last_clear,
+            rng: self.rng,
+            queue: self.queue.clone(),
+        }
+    }
+
+    fn restore(&mut self, snapshot: Snapshot) {
+        self.spawned = Some(snapshot.clone());
+        self.board = snapshot.board;
+        self.piece = snapshot.piece;
+        self.next = snapshot.next;
+        self.hold = snapshot.hold;
+        self.score = snapshot.score;
+        self.lines = snapshot.lines;
+        self.line_times = snapshot.line_times;
+        self.level = snapshot.level;
+        self.scoring = snapshot.scoring;
+        self.stats = snapshot.stats;
+        self.last_clear = snapshot.last_clear;
+        self.rng = snapshot.rng;
+        self.queue = snapshot.queue;
+        if let Some(piece_type) = self.chosen_next {
+            self.next = TetrisPiece::new(piece_type);
+        }
+        self.spawn_timer = 0;
+        self.gravity_progress = 0;
+        self.last_kick = None;
+        self.place_timer = self.timing().lock_delay;
+    }
+
+    fn next_piece(&mut self) -> TetrisPiece {
+        if let Some(queue) = &mut self.queue {
+            return match queue.is_empty() {
+                true => TetrisPiece::new(TetrisPieceType::None),
+                false => TetrisPiece::new(queue.remove(0)),
+            };
+        }
+        if let Some(piece_type) = self.chosen_next {
+            return TetrisPiece::new(piece_type);
+        }
+        if !self.mode.uses_bag() {
+            return TetrisPiece::new_random_piece(&mut self.rng);
+        }
+        if self.bag.is_empty() {
+            self.bag = TetrisPieceType::new_bag(&mut self.rng);
+        }
+        TetrisPiece::new(self.bag.remove(0))
+    }
+
+    pub fn timing(&self) -> Timing {
+        self.mode.timing(self.level)
+    }
+
+    // Whether the game is between a lock and the next piece spawning.
+    pub fn is_spawning(&self) -> bool {
+        self.spawn_timer > 0
+    }
+
+    // Carries out a player action, a finished game ignores them.
+    pub fn apply(&mut self, input: Input) {
+        if self.game_over.is_some() {
+            return;
+        }
+        match input {
+            Input::Shift(direction) => self.set_shift(direction),
+            Input::SoftDrop => self.soft_drop(),
+            Input::Rotate(cw_rotation) => self.rotate(cw_rotation),
+            Input::Hold => self.hold(),
+            Input::HardDrop => self.hard_drop(),
+            Input::Undo => {
+                self.undo();
+            }
+            Input::Redo => {
+                self.redo();
+            }
+            Input::ChooseNext(piece_type) => self.choose_next(piece_type),
+        }
+    }
+
+    // Starts or stops holding a direction. A new direction moves the piece once straight
+    // away, holding it for the timing's DAS keeps moving it every ARR ticks.
+    pub fn set_shift(&mut self, direction: i32) {
+        if direction == self.shift {
+            return;
+        }
+        self.shift = direction;
+        self.shift_timer = 0;
+        if direction != 0 {
+            self.move_piece(direction, 0);
+        }
+    }
+
+    pub fn shift(&self) -> i32 {
+        self.shift
+    }
+
+    // How close the next rising garbage row is, from 0 just after one rose to 1 when the
+    // next one comes up.
+    pub fn garbage_progress(&self) -> Option<f32> {
+        let interval = self.mode.garbage_interval(self.garbage_risen)?;
+        Some(self.garbage_timer as f32 / interval as f32)
+    }
+
+    // Moves the active piece if it fits, returns whether it moved.
+    pub fn move_piece(&mut self, dx: i32, dy: i32) -> bool {
+        let (old_x, old_y) = (self.piece.x, self.piece.y);
+        self.piece.translate(&self.board, dx, dy);
+        let moved = (old_x, old_y) != (self.piece.x, self.piece.y);
+        if moved {
+            self.last_kick = None;
+        }
+        moved
+    }
+
+    pub fn soft_drop(&mut self) {
+        if self.move_piece(0, 1) {
+            self.score += self.scoring.drop_points(1, false);
+        }
+    }
+
+    pub fn rotate(&mut self, cw_rotation: bool) {
+        if let Some(kick) = self.piece.srs_rotate(&self.board, cw_rotation) {
+            self.last_kick = Some(kick);
+        }
+    }
+
+    pub fn hold(&mut self) {
+        if self.piece.state != TetrisPieceState::Active || !self.mode.allows_hold() {
+            return;
+        }
+        if self.hold.piece_type == TetrisPieceType::None
+            && self.next.piece_type == TetrisPieceType::None
+        {
+            return;
+        }
+        self.last_kick = None;
+        if self.hold.piece_type == TetrisPieceType::None {
+            self.hold = self.piece;
+            self.piece = self.next;
+            self.piece.spawn(&self.board);
+            self.next = self.next_piece();
+        } else {
+            self.hold.y = self.piece.y;
+            self.hold.x = self.piece.x;
+
+            let temp = self.hold;
+            self.hold = self.piece;
+            self.hold.rotation = 0;
+            self.piece = temp;
+        }
+    }
+
+    pub fn hard_drop(&mut self) {
+        let mut rows = 0;
+        while self.piece.state == TetrisPieceState::Active {
+            if self.move_piece(0, 1) {
+                rows += 1;
+            } else {
+                self.piece.state = TetrisPieceState::Placing;
+            }
+        }
+        self.score += self.scoring.drop_points(rows, true);
+    }
+
+    // Where the active piece lands if it is hard dropped.
+    pub fn ghost(&self) -> TetrisPiece {
+        let mut ghost = self.piece;
+        while self.board.test_placement(&ghost) {
+            ghost.y += 1;
+        }
+        ghost.y -= 1;
+        ghost
+    }
+
+    // Advances auto shift, gravity, lock delay and the spawn delay by one tick.
+    pub fn tick(&mut self) {
+        if self.game_over.is_some() {
+            return;
+        }
+        self.ticks += 1;
+        if self.mode.time_up(self) {
+            self.game_over = Some(GameOverReason::TimeUp);
+            return;
+        }
+        if self.shift != 0 {
+            self.shift_timer += 1;
+        }
+        if let Some(interval) = self.mode.garbage_interval(self.garbage_risen) {
+            self.garbage_timer += 1;
+            if self.garbage_timer >= interval {
+                self.garbage_timer = 0;
+                self.garbage_risen += 1;
+                self.raise_garbage(1);
+                if self.game_over.is_some() {
+                    return;
+                }
+            }
+        }
+        if self.spawn_timer > 0 {
+            self.spawn_timer -= 1;
+            if self.spawn_timer > 0 {
+                return;
+            }
+            self.spawn_next();
+        }
+
+        let timing = self.timing();
+        if self.shift != 0
+            && self.shift_timer >= timing.das
+            && (self.shift_timer - timing.das).is_multiple_of(timing.arr.max(1))
+        {
+            self.move_piece(self.shift, 0);
+        }
+        if self.piece.state == TetrisPieceState::Placing {
+            self.place_timer = self.place_timer.saturating_sub(1);
+        }
+
+        self.gravity_progress += self.mode.gravity(self.level);
+        while self.gravity_progress >= GRAVITY_ONE_ROW {
+            self.gravity_progress -= GRAVITY_ONE_ROW;
+            if self.move_piece(0, 1) {
+                self.piece.state = TetrisPieceState::Active;
+                self.place_timer = timing.lock_delay;
+            } else {
+                self.piece.state = TetrisPieceState::Placing;
+                self.gravity_progress %= GRAVITY_ONE_ROW;
+            }
+        }
+
+        if self.place_timer == 0 && self.piece.state == TetrisPieceState::Placing {
+            self.lock_piece();
+        }
+    }
+
+    fn lock_piece(&mut self) {
+        if let Some(snapshot) = self.spawned.take() {
+            self.undo.push(snapshot);
+            self.redo.clear();
+        }
+        let t_spin = detect_t_spin(&self.board, &self.piece, self.last_kick);
+        self.last_kick = None;
+        self.piece.state = TetrisPieceState::Placed;
+        self.board.lock_piece(&self.piece, self.ticks);
+        let locked_above_field = self
+            .piece
+            .cells()
+            .all(|(_, y)| self.piece.y + y < self.board.hidden_rows);
+        let cleared = self.board.clear_lines();
+        let action = self
+            .scoring
+            .score_lock(cleared.rows.len() as u32, t_spin, self.level);
+        self.score += action.points;
+        self.stats.pieces += 1;
+        if action.lines >= 4 {
+            self.stats.tetrises += 1;
+        }
+        if action.t_spin != TSpin::None {
+            self.stats.t_spins += 1;
+        }
+        self.stats.max_combo = self.stats.max_combo.max(action.combo);
+        if action.lines > 0 || action.t_spin != TSpin::None {
+            self.last_clear = Some(action);
+        }
+        for _ in &cleared.rows {
+            self.lines += 1;
+            self.line_times.push(self.elapsed_ms());
+        }
+        let level = self.level;
+        self.level = self
+            .mode
+            .next_level(level, self.lines, cleared.rows.len() as u32);
+        if self.level / 100 > level / 100 {
+            self.section_times.push(self.elapsed_ms());
+        }
+        let garbage_rows = cleared.garbage_rows();
+        self.garbage_cleared += garbage_rows;
+        let refill = garbage_rows.min(self.garbage_queued);
+        if refill > 0 {
+            self.garbage_queued -= refill;
+            self.raise_garbage(refill);
+        }
+        if locked_above_field && cleared.rows.is_empty() {
+            self.top_out(GameOverReason::LockOut);
+        }
+        if self.game_over.is_some() {
+            return;
+        }
+        if let Some((rows, pieces)) = self.mode.perfect_clear_attempt() {
+            self.attempt_pieces += 1;
+            let success = self.board.is_empty();
+            if success || self.attempt_pieces >= pieces || self.board.stack_height() > rows {
+                self.perfect_clears.record(success);
+                self.start_perfect_clear_attempt();
+            }
+        }
+        if self.game_over.is_none() && self.mode.goal_reached(self) {
+            self.game_over = Some(GameOverReason::GoalReached);
+            return;
+        }
+
+        let timing = self.timing();
+        self.spawn_timer = if cleared.rows.is_empty() {
+            timing.are
+        } else {
+            timing.line_clear + timing.line_are
+        };
+        if self.spawn_timer == 0 {
+            self.spawn_next();
+        }
+    }
+
+    // Sets up the board for another perfect clear and starts over from a fresh bag, as for
+    // the first perfect clear of a game.
+    fn start_perfect_clear_attempt(&mut self) {
+        self.attempt_pieces = 0;
+        self.lay_out_perfect_clear_setup();
+        self.hold = TetrisPiece::new(TetrisPieceType::None);
+        self.bag.clear();
+        self.next = self.next_piece();
+    }
+
+    // Empties the board and puts one of the setups, picked by the game's rng, at its bottom.
+    fn lay_out_perfect_clear_setup(&mut self) {
+        self.board.clear();
+        let setup = PC_SETUPS[self.rng.gen_range(0..PC_SETUPS.len())];
+        let top = self.board.height - setup.len() as i32;
+        for (y, row) in setup.iter().enumerate() {
+            for (x, c) in row.chars().enumerate() {
+                let id = block_id(c).unwrap_or(0);
+                self.board.set_cell(x as i32, top + y as i32, id);
+            }
+        }
+    }
+
+    fn spawn_next(&mut self) {
+        if self.next.piece_type == TetrisPieceType::None {
+            self.game_over = Some(GameOverReason::OutOfPieces);
+            return;
+        }
+        self.piece = self.next;
+        self.piece.spawn(&self.board);
+        self.next = self.next_piece();
+        self.place_timer = self.timing().lock_delay;
+        self.gravity_progress = 0;
+        if !self.board.test_placement(&self.piece) {
+            self.top_out(GameOverReason::BlockOut);
+        }
+        if self.mode.allows_undo() {
+            self.spawned = Some(self.snapshot());
+        }
+    }
+
+    // Pushes garbage rows in under the stack. The active piece rides up with the stack
+    // rather than ending up inside it.
+    fn raise_garbage(&mut self, rows: u32) {
+        if self
+            .board
+            .insert_garbage_rows(rows as i32, self.mode.messiness(), &mut self.rng)
+        {
+            self.top_out(GameOverReason::GarbageOut);
+        }
+        for _ in 0..rows {
+            if self.piece.state == TetrisPieceState::Placed
+                || self.board.test_placement(&self.piece)
+            {
+                break;
+            }
+            self.piece.y -= 1;
+        }
+    }
+
+    fn top_out(&mut self, reason: GameOverReason) {
+        let enabled = match reason {
+            GameOverReason::BlockOut => self.top_out_rules.block_out,
+            GameOverReason::LockOut => self.top_out_rules.lock_out,
+            GameOverReason::GarbageOut => self.top_out_rules.garbage_out,
+            GameOverReason::GoalReached | GameOverReason::TimeUp | GameOverReason::OutOfPieces => {
+                true
+            }
+        };
+        if !enabled || self.game_over.is_some() {
+            return;
+        }
+        match reason {
+            GameOverReason::BlockOut | GameOverReason::LockOut | GameOverReason::GarbageOut
+                if self.top_out_rules.clear_board =>
+            {
+                self.board.clear();
+            }
+            _ => self.game_over = Some(reason),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game_board::GARBAGE_ID;
+    use crate::game_mode::PC_ROWS;
+    use crate::puzzle::parse_puzzles;
+
+    // Fills the visible field except for one column so nothing clears.
+    fn stacked_board() -> GameBoard {
+        let mut board = GameBoard::with_hidden_rows(10, 20, 2);
+        for y in 2..22 {
+            for x in 1..10 {
+                board.set_cell(x, y, 1);
+            }
+        }
+        board
+    }
+
+    fn lock(game: &mut TetrisGame) {
+        game.hard_drop();
+        for _ in 0..game.timing().lock_delay {
+            game.tick();
+        }
+    }
+
+    #[test]
+    fn test_lock_out() {
+        let mut game = TetrisGame::new(stacked_board(), GameMode::Endless, 0);
+        game.piece = TetrisPiece::new(TetrisPieceType::O);
+        game.piece.spawn(&game.board);
+        game.hard_drop();
+        let (mut piece, next) = (game.piece, game.next);
+        piece.state = TetrisPieceState::Placed;
+        lock(&mut game);
+        assert_eq!(game.game_over, Some(GameOverReason::LockOut));
+        // The finished game doesn't go on to spawn the next piece.
+        assert_eq!((game.piece, game.next), (piece, next));
+
+        // Nor does an empty queue turn it into running out of pieces.
+        let mut game = TetrisGame::new(stacked_board(), GameMode::Endless, 0);
+        game.piece = TetrisPiece::new(TetrisPieceType::O);
+        game.piece.spawn(&game.board);
+        game.next = TetrisPiece::new(TetrisPieceType::None);
+        lock(&mut game);
+        assert_eq!(game.game_over, Some(GameOverReason::LockOut));
+    }
+
+    #[test]
+    fn test_block_out() {
+        let mut board = GameBoard::with_hidden_rows(10, 20, 2);
+        for x in 0..10 {
+            board.set_cell(x, 1, if x == 9 { 0 } else { 1 });
+        }
+        let mut game = TetrisGame::new(board, GameMode::Endless, 0);
+        game.piece = TetrisPiece::new(TetrisPieceType::I);
+        game.piece.x = 7;
+        game.piece.rotation = 1;
+        lock(&mut game);
+        assert_eq!(game.game_over, Some(GameOverReason::BlockOut));
+    }
+
+    #[test]
+    fn test_disabled_top_out() {
+        let rules = TopOutRules {
+            lock_out: false,
+            block_out: false,
+            ..TopOutRules::default()
+        };
+        let mut game = TetrisGame::new(stacked_board(), GameMode::Endless, 0);
+        game.top_out_rules = rules;
+        game.piece = TetrisPiece::new(TetrisPieceType::O);
+        game.piece.spawn(&game.board);
+        lock(&mut game);
+        assert_eq!(game.game_over, None);
+    }
+
+    #[test]
+    fn test_sprint_goal() {
+        let mut board = GameBoard::with_hidden_rows(10, 20, 2);
+        for x in 4..10 {
+            board.set_cell(x, 21, 1);
+        }
+        let mut game = TetrisGame::new(board, GameMode::Sprint { lines: 1 }, 0);
+        game.piece = TetrisPiece::new(TetrisPieceType::I);
+        game.piece.spawn(&game.board);
+        game.piece.x = -1;
+        lock(&mut game);
+        assert_eq!(game.game_over, Some(GameOverReason::GoalReached));
+        assert_eq!(game.lines, 1);
+        assert_eq!(game.line_times, vec![game.elapsed_ms()]);
+    }
+
+    #[test]
+    fn test_dig_refills_garbage() {
+        let mode = GameMode::Dig {
+            rows: 12,
+            messiness: 0,
+        };
+        let mut game = TetrisGame::new(GameBoard::with_hidden_rows(10, 20, 2), mode, 3);
+        assert_eq!(game.garbage_queued, 2);
+        let hole = (0..10).find(|x| game.board.cell(*x, 21) == 0).unwrap();
+        assert_eq!(game.board.cell(hole, 12), 0);
+        assert_eq!(game.board.cell(hole, 11), 0);
+        game.piece = TetrisPiece::new(TetrisPieceType::I);
+        game.piece.rotation = 1;
+        game.piece.x = hole - 2;
+        lock(&mut game);
+        assert_eq!(game.garbage_cleared, 4);
+        assert_eq!(game.garbage_queued, 0);
+        assert_eq!(game.board.cell((hole + 1) % 10, 14), GARBAGE_ID);
+        assert_eq!(game.board.cell((hole + 1) % 10, 13), 0);
+        assert_eq!(game.game_over, None);
+    }
+
+    #[test]
+    fn test_survival_garbage_out() {
+        let mut board = GameBoard::with_hidden_rows(10, 20, 2);
+        board.set_cell(0, 0, 1);
+        let mut game = TetrisGame::new(board, GameMode::Survival, 0);
+        let interval = GameMode::Survival.garbage_interval(0).unwrap();
+        for _ in 0..interval - 1 {
+            game.tick();
+        }
+        assert_eq!(
+            game.garbage_progress(),
+            Some((interval - 1) as f32 / interval as f32)
+        );
+        game.tick();
+        assert_eq!(game.garbage_risen, 1);
+        assert_eq!(game.game_over, Some(GameOverReason::GarbageOut));
+    }
+
+    #[test]
+    fn test_master_delays() {
+        let mut game = TetrisGame::new(GameBoard::with_hidden_rows(10, 20, 2), GameMode::Master, 0);
+        let timing = game.timing();
+        let next = game.next.piece_type;
+        lock(&mut game);
+        assert_eq!(game.level, 1);
+        assert!(game.is_spawning());
+        for _ in 1..timing.are {
+            game.tick();
+        }
+        assert!(game.is_spawning());
+        game.tick();
+        assert!(!game.is_spawning());
+        assert_eq!(game.piece.piece_type, next);
+        // At 20G the new piece is on the floor the tick it spawns.
+        assert_eq!(game.piece.state, TetrisPieceState::Placing);
+        assert!(!game.board.test_placement(&TetrisPiece {
+            y: game.piece.y + 1,
+            ..game.piece
+        }));
+    }
+
+    #[test]
+    fn test_ghost() {
+        let mut game = TetrisGame::new(stacked_board(), GameMode::Endless, 0);
+        game.piece = TetrisPiece::new(TetrisPieceType::I);
+        game.piece.x = -2;
+        game.piece.rotation = 1;
+        let ghost = game.ghost();
+        assert_eq!((ghost.x, ghost.rotation), (-2, 1));
+        game.hard_drop();
+        assert_eq!(ghost.y, game.piece.y);
+        assert_eq!(ghost.cells().map(|(_, y)| ghost.y + y).max(), Some(21));
+    }
+
+    #[test]
+    fn test_auto_shift() {
+        let mut game =
+            TetrisGame::new(GameBoard::with_hidden_rows(10, 20, 2), GameMode::Endless, 0);
+        game.piece = TetrisPiece::new(TetrisPieceType::O);
+        game.piece.spawn(&game.board);
+        let x = game.piece.x;
+        let timing = game.timing();
+        game.set_shift(-1);
+        assert_eq!(game.piece.x, x - 1);
+        for _ in 1..timing.das {
+            game.tick();
+        }
+        assert_eq!(game.piece.x, x - 1);
+        game.tick();
+        assert_eq!(game.piece.x, x - 2);
+        for _ in 0..timing.arr {
+            game.tick();
+        }
+        assert_eq!(game.piece.x, x - 3);
+        game.set_shift(0);
+        for _ in 0..timing.das {
+            game.tick();
+        }
+        assert_eq!(game.piece.x, x - 3);
+    }
+
+    #[test]
+    fn test_fading_stack() {
+        let mut game =
+            TetrisGame::new(GameBoard::with_hidden_rows(10, 20, 2), GameMode::Endless, 0);
+        game.visibility = StackVisibility::Fading { ticks: 100 };
+        game.piece = TetrisPiece::new(TetrisPieceType::O);
+        game.piece.spawn(&game.board);
+        let x = game.piece.x + 2;
+        lock(&mut game);
+        assert_eq!(game.block_alpha(x, 21), 255);
+        for _ in 0..100 + FADE_TICKS / 2 {
+            game.tick();
+        }
+        assert!((100..=160).contains(&game.block_alpha(x, 21)));
+        for _ in 0..FADE_TICKS {
+            game.tick();
+        }
+        assert_eq!(game.block_alpha(x, 21), 0);
+        game.game_over = Some(GameOverReason::BlockOut);
+        assert_eq!(game.block_alpha(x, 21), 255);
+    }
+
+    #[test]
+    fn test_zen_undo_redo() {
+        let mode = GameMode::Zen { gravity: 0 };
+        let mut game = TetrisGame::new(GameBoard::with_hidden_rows(10, 20, 2), mode, 5);
+        game.choose_next(Some(TetrisPieceType::O));
+        let first = game.piece;
+        lock(&mut game);
+        let after_first = game.board.grid().to_vec();
+        lock(&mut game);
+        assert_eq!(game.piece.piece_type, TetrisPieceType::O);
+        assert_eq!(game.stats.pieces, 2);
+        assert!(game.undo());
+        assert!(game.undo());
+        assert!(!game.undo());
+        assert_eq!(game.piece, first);
+        assert!(game.board.grid().iter().all(|id| *id == 0));
+        assert!(game.redo());
+        assert_eq!(game.board.grid(), &after_first[..]);
+        assert_eq!(game.stats.pieces, 1);
+        lock(&mut game);
+        assert!(!game.redo());
+        assert_eq!(game.undo_count(), 2);
+    }
+
+    #[test]
+    fn test_top_out_clears_board() {
+        let mut game = TetrisGame::new(stacked_board(), GameMode::Zen { gravity: 0 }, 0);
+        game.piece = TetrisPiece::new(TetrisPieceType::O);
+        game.piece.spawn(&game.board);
+        lock(&mut game);
+        assert_eq!(game.game_over, None);
+        assert!(game.board.grid().iter().all(|id| *id == 0));
+    }
+
+    fn puzzle_game(name: &str) -> TetrisGame {
+        let puzzles = parse_puzzles(include_str!("../puzzles.txt")).unwrap();
+        let (number, puzzle) = puzzles
+            .iter()
+            .enumerate()
+            .find(|(_, p)| p.name == name)
+            .unwrap();
+        let mode = GameMode::Puzzle {
+            number: number as u32 + 1,
+            id: puzzle.id(),
+            goal: puzzle.goal,
+            hold: puzzle.hold,
+        };
+        let mut game = TetrisGame::new(puzzle.board(20, 2), mode, 0);
+        game.set_queue(&puzzle.queue);
+        game
+    }
+
+    #[test]
+    fn test_puzzle_t_spin_triple() {
+        let mut game = puzzle_game("T-spin triple");
+        assert_eq!(game.pieces_left(), Some(0));
+        while game.move_piece(0, 1) {}
+        game.move_piece(-1, 0);
+        game.move_piece(-1, 0);
+        game.rotate(true);
+        lock(&mut game);
+        assert_eq!(game.lines, 3);
+        assert_eq!(game.game_over, Some(GameOverReason::GoalReached));
+    }
+
+    #[test]
+    fn test_puzzle_out_of_pieces() {
+        let mut game = puzzle_game("Tetris");
+        game.hold();
+        assert_eq!(game.hold.piece_type, TetrisPieceType::None);
+        lock(&mut game);
+        assert_eq!(game.lines, 0);
+        assert_eq!(game.game_over, Some(GameOverReason::OutOfPieces));
+    }
+
+    // Whether the board holds nothing but one of the perfect clear setups.
+    fn has_setup(board: &GameBoard) -> bool {
+        let top = board.height - PC_ROWS;
+        (0..top).all(|y| (0..board.width).all(|x| board.cell(x, y) == 0))
+            && PC_SETUPS.iter().any(|setup| {
+                setup.iter().enumerate().all(|(y, row)| {
+                    row.chars().enumerate().all(|(x, c)| {
+                        board.cell(x as i32, top + y as i32) == block_id(c).unwrap_or(0)
+                    })
+                })
+            })
+    }
+
+    #[test]
+    fn test_perfect_clear_attempts() {
+        let board = GameBoard::with_hidden_rows(10, 20, 2);
+        let mut game = TetrisGame::new(board, GameMode::PerfectClear, 3);
+        assert!(has_setup(&game.board));
+        while game.perfect_clears.attempts == 0 {
+            lock(&mut game);
+        }
+        assert_eq!(game.perfect_clears.successes, 0);
+        assert!(has_setup(&game.board));
+        assert_eq!(game.game_over, None);
+
+        game.board.clear();
+        let bottom = game.board.height - 1;
+        for x in [0, 1, 2, 7, 8, 9] {
+            game.board.set_cell(x, bottom, GARBAGE_ID);
+        }
+        game.piece = TetrisPiece::new(TetrisPieceType::I);
+        game.piece.spawn(&game.board);
+        lock(&mut game);
+        assert_eq!(game.perfect_clears.successes, 1);
+        assert_eq!(game.perfect_clears.streak, 1);
+        assert_eq!(game.perfect_clears.success_rate(), 50);
+        assert_eq!(game.attempt_pieces, 0);
+    }
+}
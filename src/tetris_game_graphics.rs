@@ -3,37 +3,258 @@ use sdl2::pixels::Palette;
 use sdl2::pixels::PixelFormat;
 use sdl2::pixels::PixelFormatEnum;
 use sdl2::rect::Rect;
+use sdl2::render::BlendMode;
 use sdl2::render::Canvas;
 use sdl2::render::TextureCreator;
 use sdl2::surface::Surface;
 use sdl2::video::Window;
 use sdl2::video::WindowContext;
 
+use crate::game_board::GARBAGE_ID;
 use crate::tetris_piece::TetrisPieceType;
 use crate::GameBoard;
 use crate::TetrisPiece;
 
+// Where the playfield and the side panels go for a given board size, in logical pixels.
+// The window scales these up to its size, pictures are drawn at them one to one.
+#[derive(Debug, Copy, Clone)]
+pub struct Layout {
+    pub board_x: i32,
+    pub board_y: i32,
+    pub board_width: i32,
+    pub board_height: i32,
+    pub hidden_rows: i32,
+    pub hold_x: i32,
+    pub next_x: i32,
+    pub panel_y: i32,
+    pub score_y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+// Space around the panels and the playfield frame, and between the panels on the right.
+const MARGIN: i32 = 32;
+const PANEL_GAP: i32 = 16;
+
+impl Layout {
+    pub fn new(board: &GameBoard) -> Layout {
+        let board_width = board.width * 16;
+        let board_height = board.visible_height() * 16;
+        let hold_x = MARGIN;
+        let board_x = hold_x + HOLD_PANEL.2 as i32 + MARGIN;
+        let board_y = MARGIN + FRAME_BORDER;
+        let next_x = board_x + board_width + MARGIN;
+        let panel_y = MARGIN;
+        let score_y = panel_y + NEXT_PANEL.3 as i32 + PANEL_GAP;
+        Layout {
+            board_x,
+            board_y,
+            board_width,
+            board_height,
+            hidden_rows: board.hidden_rows,
+            hold_x,
+            next_x,
+            panel_y,
+            score_y,
+            width: next_x + NEXT_PANEL.2 as i32 + MARGIN,
+            height: (board_y + board_height + MARGIN).max(score_y + SCORE_PANEL.3 as i32 + MARGIN),
+        }
+    }
+
+    // Top left of the score's digits in the score panel.
+    pub fn score_text(&self) -> (i32, i32) {
+        (self.next_x + 19, self.score_y + 21)
+    }
+
+    // The darkened box with the mode's stats, below the hold panel and as wide as it.
+    pub fn stats_backdrop(&self, lines: usize) -> Rect {
+        Rect::new(
+            self.hold_x,
+            self.score_y,
+            HOLD_PANEL.2,
+            lines as u32 * 10 + 6,
+        )
+    }
+
+    // Pieces of foreground_gfx.png as source and destination rectangles: the side panels
+    // and the playfield frame, cut in nine so that it stretches around the board.
+    pub fn frame_pieces(&self) -> Vec<(Rect, Rect)> {
+        let mut pieces = Vec::new();
+        for (src, x, y) in [
+            (HOLD_PANEL, self.hold_x, self.panel_y),
+            (NEXT_PANEL, self.next_x, self.panel_y),
+            (SCORE_PANEL, self.next_x, self.score_y),
+        ] {
+            pieces.push((Rect::from(src), Rect::new(x, y, src.2, src.3)));
+        }
+
+        let (frame_x, frame_y, frame_width, frame_height) = BOARD_FRAME;
+        let columns = [
+            (
+                frame_x,
+                FRAME_BORDER,
+                self.board_x - FRAME_BORDER,
+                FRAME_BORDER,
+            ),
+            (
+                frame_x + FRAME_BORDER,
+                frame_width as i32 - 2 * FRAME_BORDER,
+                self.board_x,
+                self.board_width,
+            ),
+            (
+                frame_x + frame_width as i32 - FRAME_BORDER,
+                FRAME_BORDER,
+                self.board_x + self.board_width,
+                FRAME_BORDER,
+            ),
+        ];
+        let rows = [
+            (
+                frame_y,
+                FRAME_BORDER,
+                self.board_y - FRAME_BORDER,
+                FRAME_BORDER,
+            ),
+            (
+                frame_y + FRAME_BORDER,
+                frame_height as i32 - 2 * FRAME_BORDER,
+                self.board_y,
+                self.board_height,
+            ),
+            (
+                frame_y + frame_height as i32 - FRAME_BORDER,
+                FRAME_BORDER,
+                self.board_y + self.board_height,
+                FRAME_BORDER,
+            ),
+        ];
+        for (src_x, src_width, dest_x, dest_width) in columns {
+            for (src_y, src_height, dest_y, dest_height) in rows {
+                pieces.push((
+                    Rect::new(src_x, src_y, src_width as u32, src_height as u32),
+                    Rect::new(dest_x, dest_y, dest_width as u32, dest_height as u32),
+                ));
+            }
+        }
+        pieces
+    }
+
+    // A bar along the left edge of the board that fills up until the next garbage row
+    // rises, turning red shortly before it does.
+    pub fn garbage_meter(&self, progress: f32) -> (Rect, Color) {
+        let height = (self.board_height as f32 * progress.clamp(0.0, 1.0)) as i32;
+        let meter = Rect::new(
+            self.board_x - 6,
+            self.board_y + self.board_height - height,
+            4,
+            height as u32,
+        );
+        let color = if progress > 0.75 {
+            Color::RGB(229, 28, 35)
+        } else {
+            Color::RGB(253, 216, 53)
+        };
+        (meter, color)
+    }
+}
+
+// Sprite sheets in base_gfx.png as x, y, width and height: the seven blocks, the font in
+// two rows and the frames of the shine effect.
+pub const BLOCK_SHEET: (i32, i32, u32, u32) = (0, 0, 16 * 7, 16);
+pub const FONT_SHEET: (i32, i32, u32, u32) = (0, 16, 6 * 36, 6);
+pub const SYMBOL_SHEET: (i32, i32, u32, u32) = (0, 23, 6 * 38, 6);
+pub const SHINE_SHEET: (i32, i32, u32, u32) = (0, 64, 16 * 7, 16);
+
+// Pixels of this color in base_gfx.png are transparent.
+pub const COLOR_KEY: Color = Color::RGBA(246, 153, 136, 255);
+
+// Source rectangle of sprite index of a sheet, counting along rows like
+// SpriteSheet::new_from_surface_with_rect.
+pub fn sprite_rect(
+    sheet: (i32, i32, u32, u32),
+    sprite_width: u32,
+    sprite_height: u32,
+    index: u32,
+) -> Rect {
+    let columns = sheet.2 / sprite_width;
+    Rect::new(
+        sheet.0 + (index % columns * sprite_width) as i32,
+        sheet.1 + (index / columns * sprite_height) as i32,
+        sprite_width,
+        sprite_height,
+    )
+}
+
+// Index of a character in the font, which holds A-Z, 1-9, 0 followed by a-z and
+// !@#$%^&*()-+.
+pub fn font_index(c: char) -> Option<u32> {
+    match c {
+        'A'..='Z' => Some(c as u32 - 'A' as u32),
+        '1'..='9' => Some(c as u32 - '1' as u32 + 26),
+        '0' => Some(35),
+        'a'..='z' => Some(c as u32 - 'a' as u32 + 36),
+        '-' => Some(72),
+        '+' => Some(73),
+        _ => None,
+    }
+}
+
+// Source rectangle of a character of the font, which starts on one row of base_gfx.png
+// and carries on on the next.
+pub fn font_rect(index: u32) -> Rect {
+    let letters = FONT_SHEET.2 / 6;
+    if index < letters {
+        sprite_rect(FONT_SHEET, 6, 6, index)
+    } else {
+        sprite_rect(SYMBOL_SHEET, 6, 6, index - letters)
+    }
+}
+
+// Colons and periods are not in the font and are drawn as dots, at these rows of the
+// character.
+pub fn font_dots(c: char) -> &'static [i32] {
+    match c {
+        ':' => &[1, 4],
+        '.' => &[4],
+        _ => &[],
+    }
+}
+
+// How far a piece is moved back from the corner of the hold and next panels so that it
+// sits in the middle.
+pub fn preview_offset(piece_type: TetrisPieceType) -> (i32, i32) {
+    match piece_type {
+        TetrisPieceType::I => (16, 8),
+        TetrisPieceType::O => (16, 0),
+        _ => (8, 0),
+    }
+}
+
+// Source rectangles of the panels and the playfield frame in foreground_gfx.png.
+pub const HOLD_PANEL: (i32, i32, u32, u32) = (32, 32, 96, 96);
+pub const NEXT_PANEL: (i32, i32, u32, u32) = (352, 32, 96, 96);
+pub const SCORE_PANEL: (i32, i32, u32, u32) = (352, 144, 96, 48);
+pub const BOARD_FRAME: (i32, i32, u32, u32) = (144, 32, 192, 352);
+pub const FRAME_BORDER: i32 = 16;
+
+// Draws a game onto a canvas whose logical size is the layout's, so every coordinate
+// here is in logical pixels and SDL takes care of scaling them to the window.
 pub struct TetrisGameGraphics<'a> {
     canvas: &'a mut Canvas<Window>,
-    sprite_width_mult: u32,
-    sprite_height_mult: u32,
+    pub layout: Layout,
 }
 
 impl<'a> TetrisGameGraphics<'_> {
-    pub fn new(
-        canvas: &'a mut Canvas<Window>,
-        sprite_width_mult: u32,
-        sprite_height_mult: u32,
-    ) -> TetrisGameGraphics<'a> {
-        TetrisGameGraphics {
-            canvas,
-            sprite_width_mult,
-            sprite_height_mult,
-        }
+    pub fn new(canvas: &'a mut Canvas<Window>, layout: Layout) -> TetrisGameGraphics<'a> {
+        TetrisGameGraphics { canvas, layout }
     }
 
     pub fn draw_background(&mut self, elements: Vec<&Surface>) {
-        for (_i, element) in elements.iter().enumerate() {
+        // Clears the bars around the game when the window's shape doesn't match it.
+        self.canvas.set_draw_color(Color::RGB(0, 0, 0));
+        self.canvas.clear();
+        for element in elements.iter() {
             self.canvas
                 .copy(
                     &element.as_texture(&self.canvas.texture_creator()).unwrap(),
@@ -44,30 +265,81 @@ impl<'a> TetrisGameGraphics<'_> {
         }
     }
 
+    // Draws the side panels and a playfield frame stretched around the board.
+    pub fn draw_frames(&mut self, foreground: &Surface) {
+        let texture_creator = self.canvas.texture_creator();
+        let texture = foreground.as_texture(&texture_creator).unwrap();
+        for (src_rect, dest_rect) in self.layout.frame_pieces() {
+            self.canvas.copy(&texture, src_rect, dest_rect).unwrap();
+        }
+    }
+
+    // Draws the visible rows of the board, alpha gives the opacity of each locked block.
     pub fn draw_game_board(
         &mut self,
-        x_offset: i32,
-        y_offset: i32,
         board: &GameBoard,
-        sprite_sheet: &SpriteSheet,
+        sprite_sheet: &mut SpriteSheet,
+        alpha: impl Fn(i32, i32) -> u8,
     ) {
-        for y in 0..board.height {
+        for y in board.hidden_rows..board.height {
             for x in 0..board.width {
-                let piece_id = board.grid[(x + y * board.width) as usize];
-                if piece_id > 0 {
-                    let sprite = &sprite_sheet.sprites[(piece_id - 1) as usize];
-                    let dest_rect = Rect::new(
-                        (x * 16 + x_offset) * self.sprite_width_mult as i32,
-                        (y * 16 + y_offset) * self.sprite_height_mult as i32,
-                        16 * self.sprite_width_mult,
-                        16 * self.sprite_height_mult,
-                    );
+                let piece_id = board.cell(x, y);
+                let alpha = if piece_id > 0 { alpha(x, y) } else { 0 };
+                if alpha == 0 {
+                    continue;
+                }
+                let dest_rect = Rect::new(
+                    x * 16 + self.layout.board_x,
+                    (y - board.hidden_rows) * 16 + self.layout.board_y,
+                    16,
+                    16,
+                );
+                if piece_id == GARBAGE_ID {
+                    // The sprite sheet has no garbage block, draw a plain gray one.
+                    self.canvas.set_blend_mode(BlendMode::Blend);
+                    self.canvas.set_draw_color(Color::RGBA(96, 96, 96, alpha));
+                    self.canvas.fill_rect(dest_rect).unwrap();
+                    self.canvas
+                        .set_draw_color(Color::RGBA(128, 128, 128, alpha));
+                    self.canvas.draw_rect(dest_rect).unwrap();
+                    self.canvas.set_blend_mode(BlendMode::None);
+                } else {
+                    let sprite = &mut sprite_sheet.sprites[(piece_id - 1) as usize];
+                    sprite.texture.set_blend_mode(BlendMode::Blend);
+                    sprite.texture.set_alpha_mod(alpha);
                     self.canvas.copy(&sprite.texture, None, dest_rect).unwrap();
+                    sprite.texture.set_alpha_mod(255);
                 };
             }
         }
     }
 
+    fn set_board_clip(&mut self, enabled: bool) {
+        let clip_rect = if enabled {
+            Some(Rect::new(
+                self.layout.board_x,
+                self.layout.board_y,
+                self.layout.board_width as u32,
+                self.layout.board_height as u32,
+            ))
+        } else {
+            None
+        };
+        self.canvas.set_clip_rect(clip_rect);
+    }
+
+    // Draws a piece that sits on the board, cells inside the vanish zone are clipped.
+    pub fn draw_board_piece(&mut self, piece: &TetrisPiece, sprite_sheet: &SpriteSheet) {
+        self.set_board_clip(true);
+        self.draw_piece(
+            self.layout.board_x + piece.x * 16,
+            self.layout.board_y + (piece.y - self.layout.hidden_rows) * 16,
+            piece,
+            sprite_sheet,
+        );
+        self.set_board_clip(false);
+    }
+
     pub fn draw_piece(
         &mut self,
         x_offset: i32,
@@ -75,88 +347,53 @@ impl<'a> TetrisGameGraphics<'_> {
         piece: &TetrisPiece,
         sprite_sheet: &SpriteSheet,
     ) {
-        for y in 0..5 {
-            for x in 0..5 {
-                let piece_id = piece.grid[(x + y * 5) as usize];
-                if piece_id > 0 {
-                    let sprite = &sprite_sheet.sprites[(piece_id - 1) as usize];
-                    let dest_rect = Rect::new(
-                        (x * 16 + x_offset) * self.sprite_width_mult as i32,
-                        (y * 16 + y_offset) * self.sprite_height_mult as i32,
-                        16 * self.sprite_width_mult,
-                        16 * self.sprite_height_mult,
-                    );
-                    self.canvas.copy(&sprite.texture, None, dest_rect).unwrap();
-                }
-            }
+        let piece_id = piece.piece_type.id();
+        for (x, y) in piece.cells() {
+            let sprite = &sprite_sheet.sprites[(piece_id - 1) as usize];
+            let dest_rect = Rect::new(x * 16 + x_offset, y * 16 + y_offset, 16, 16);
+            self.canvas.copy(&sprite.texture, None, dest_rect).unwrap();
         }
     }
 
-    pub fn draw_hold_piece(
-        &mut self,
-        piece: &TetrisPiece,
-        sprite_sheet: &SpriteSheet,
-    ) {
-        let mut fudge_x = 8;
-        let mut fudge_y = 0;
-        if piece.piece_type == TetrisPieceType::I {
-            fudge_x = 16;
-            fudge_y = 8;
-        }
-        if piece.piece_type == TetrisPieceType::O {
-            fudge_x = 16;
-            fudge_y = 0;
-        }
-        self.draw_piece(3 * 16 - fudge_x, 3 * 16 - fudge_y, &piece, &sprite_sheet);
+    pub fn draw_hold_piece(&mut self, piece: &TetrisPiece, sprite_sheet: &SpriteSheet) {
+        let (fudge_x, fudge_y) = preview_offset(piece.piece_type);
+        self.draw_piece(
+            self.layout.hold_x + 16 - fudge_x,
+            self.layout.panel_y + 16 - fudge_y,
+            piece,
+            sprite_sheet,
+        );
     }
 
     pub fn draw_next_piece(&mut self, piece: &TetrisPiece, sprite_sheet: &SpriteSheet) {
-      let mut fudge_x = 8;
-      let mut fudge_y = 0;
-      if piece.piece_type == TetrisPieceType::I {
-          fudge_x = 16;
-          fudge_y = 8;
-      }
-      if piece.piece_type == TetrisPieceType::O {
-          fudge_x = 16;
-          fudge_y = 0;
-      }
-      self.draw_piece(23 * 16 - fudge_x, 3 * 16 - fudge_y, &piece, &sprite_sheet);
-
+        let (fudge_x, fudge_y) = preview_offset(piece.piece_type);
+        self.draw_piece(
+            self.layout.next_x + 16 - fudge_x,
+            self.layout.panel_y + 16 - fudge_y,
+            piece,
+            sprite_sheet,
+        );
     }
 
     pub fn draw_shine_effect(
         &mut self,
-        x_offset: i32,
-        y_offset: i32,
         piece: &TetrisPiece,
         sprite_sheet: &SpriteSheet,
         frame: i32,
     ) {
-        for y in 0..5 {
-            for x in 0..5 {
-                let piece_id = piece.grid[(x + y * 5) as usize];
-                if piece_id > 0 {
-                    let sprite = &sprite_sheet.sprites[frame as usize];
-                    let dest_rect = Rect::new(
-                        (x * 16 + x_offset) * self.sprite_width_mult as i32,
-                        (y * 16 + y_offset) * self.sprite_height_mult as i32,
-                        16 * self.sprite_width_mult,
-                        16 * self.sprite_height_mult,
-                    );
-                    self.canvas.copy(&sprite.texture, None, dest_rect).unwrap();
-                }
-            }
+        let x_offset = self.layout.board_x + piece.x * 16;
+        let y_offset = self.layout.board_y + (piece.y - self.layout.hidden_rows) * 16;
+        self.set_board_clip(true);
+        for (x, y) in piece.cells() {
+            let sprite = &sprite_sheet.sprites[frame as usize];
+            let dest_rect = Rect::new(x * 16 + x_offset, y * 16 + y_offset, 16, 16);
+            self.canvas.copy(&sprite.texture, None, dest_rect).unwrap();
         }
+        self.set_board_clip(false);
     }
 
-    pub fn draw_score(
-        &mut self,
-        x_offset: i32,
-        y_offset: i32,
-        score: i32,
-        sprite_sheet: &SpriteSheet,
-    ) {
+    pub fn draw_score(&mut self, score: i32, sprite_sheet: &SpriteSheet) {
+        let (x_offset, y_offset) = self.layout.score_text();
         let score_str = format!("{:010}", score);
         for (i, c) in score_str.chars().enumerate() {
             let mut sprite_index = c.to_digit(10).unwrap() + 25;
@@ -164,21 +401,63 @@ impl<'a> TetrisGameGraphics<'_> {
                 sprite_index = 35;
             }
             let sprite = &sprite_sheet.sprites[sprite_index as usize];
-            let dest_rect = Rect::new(
-                (x_offset + i as i32 * 6) * self.sprite_width_mult as i32,
-                (y_offset + 6) * self.sprite_height_mult as i32,
-                6 * self.sprite_width_mult,
-                6 * self.sprite_height_mult,
-            );
+            let dest_rect = Rect::new(x_offset + i as i32 * 6, y_offset, 6, 6);
+            self.canvas.copy(&sprite.texture, None, dest_rect).unwrap();
+        }
+    }
+
+    // Draws text with the 6x6 font, characters that aren't in it are skipped.
+    pub fn draw_text(
+        &mut self,
+        x_offset: i32,
+        y_offset: i32,
+        text: &str,
+        font_sheet: &SpriteSheet,
+    ) {
+        self.canvas.set_draw_color(Color::RGB(255, 255, 255));
+        for (i, c) in text.chars().enumerate() {
+            let x = x_offset + i as i32 * 6;
+            for dot_y in font_dots(c) {
+                let dot = Rect::new(x + 2, y_offset + dot_y, 1, 1);
+                self.canvas.fill_rect(dot).unwrap();
+            }
+            let Some(sprite_index) = font_index(c) else {
+                continue;
+            };
+            let sprite = &font_sheet.sprites[sprite_index as usize];
+            let dest_rect = Rect::new(x, y_offset, 6, 6);
             self.canvas.copy(&sprite.texture, None, dest_rect).unwrap();
         }
     }
 
+    // Draws mode specific stats below the hold panel on a darkened backdrop.
+    pub fn draw_stats(&mut self, lines: &[String], font_sheet: &SpriteSheet) {
+        if lines.is_empty() {
+            return;
+        }
+        let backdrop = self.layout.stats_backdrop(lines.len());
+        let (x, y) = (backdrop.x(), backdrop.y());
+        self.canvas.set_blend_mode(BlendMode::Blend);
+        self.canvas.set_draw_color(Color::RGBA(0, 0, 0, 160));
+        self.canvas.fill_rect(backdrop).unwrap();
+        self.canvas.set_blend_mode(BlendMode::None);
+        for (i, line) in lines.iter().enumerate() {
+            self.draw_text(x + 4, y + 4 + i as i32 * 10, line, font_sheet);
+        }
+    }
+
+    pub fn draw_garbage_meter(&mut self, progress: f32) {
+        let (meter, color) = self.layout.garbage_meter(progress);
+        self.canvas.set_draw_color(color);
+        self.canvas.fill_rect(meter).unwrap();
+    }
+
     pub fn present(&mut self) {
         self.canvas.present();
     }
 }
 
+#[allow(dead_code)]
 pub struct Sprite<'a> {
     pub texture: sdl2::render::Texture<'a>,
     pub width: u32,
@@ -220,11 +499,9 @@ impl<'a> SpriteSheet<'_> {
                 let mut sprite_surface =
                     Surface::new(sprite_width, sprite_height, PixelFormatEnum::RGBA32).unwrap();
                 //sprite_surface.set_palette(&Palette::from_surface(&surface)).unwrap();
-                sprite_surface
-                    .set_color_key(true, Color::RGBA(246, 153, 136, 255))
-                    .unwrap();
+                sprite_surface.set_color_key(true, COLOR_KEY).unwrap();
                 surface.blit(src_rect, &mut sprite_surface, None).unwrap();
-                let sprite_texture = sprite_surface.as_texture(&texture_creator).unwrap();
+                let sprite_texture = sprite_surface.as_texture(texture_creator).unwrap();
                 sprites.push(Sprite::new(sprite_texture));
             }
         }
@@ -232,6 +509,7 @@ impl<'a> SpriteSheet<'_> {
     }
 }
 
+#[allow(dead_code)]
 pub trait GetPalette {
     fn from_surface(surface: &Surface) -> Palette;
 }
@@ -249,3 +527,42 @@ impl GetPalette for Palette {
         Palette::with_colors(&colors).unwrap()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sprite_rects() {
+        assert_eq!(
+            sprite_rect(BLOCK_SHEET, 16, 16, 3),
+            Rect::new(48, 0, 16, 16)
+        );
+        assert_eq!(font_rect(font_index('B').unwrap()), Rect::new(6, 16, 6, 6));
+        assert_eq!(
+            font_rect(font_index('0').unwrap()),
+            Rect::new(210, 16, 6, 6)
+        );
+        assert_eq!(font_rect(font_index('b').unwrap()), Rect::new(6, 23, 6, 6));
+        assert_eq!(
+            font_rect(font_index('+').unwrap()),
+            Rect::new(222, 23, 6, 6)
+        );
+        assert_eq!(font_index(':'), None);
+        assert_eq!(font_dots(':'), &[1, 4]);
+    }
+
+    #[test]
+    fn test_frame_pieces() {
+        let layout = Layout::new(&GameBoard::with_hidden_rows(10, 20, 20));
+        let pieces = layout.frame_pieces();
+        assert_eq!(pieces.len(), 3 + 9);
+        // The middle of the frame is stretched over the board.
+        assert_eq!(pieces[3 + 4].1, Rect::new(160, 48, 160, 320));
+        let (meter, _) = layout.garbage_meter(0.5);
+        assert_eq!(meter, Rect::new(154, 208, 4, 160));
+        assert_eq!((layout.width, layout.height), (480, 400));
+        assert_eq!(layout.score_text(), (371, 165));
+        assert_eq!(layout.stats_backdrop(2), Rect::new(32, 144, 96, 26));
+    }
+}
@@ -1,10 +1,13 @@
 use core::fmt;
+use rand::seq::SliceRandom;
 use rand::Rng;
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
 
+use crate::game_board::block_char;
 use crate::GameBoard;
 
-#[derive(Debug, Copy, Clone, PartialEq)]
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
 pub enum TetrisPieceType {
     I,
     O,
@@ -16,108 +19,215 @@ pub enum TetrisPieceType {
     None,
 }
 
-#[derive(Debug, PartialEq)]
+impl TetrisPieceType {
+    // Block id written into the board grid and used to pick the sprite.
+    pub fn id(&self) -> i32 {
+        match self {
+            TetrisPieceType::None => 0,
+            _ => *self as i32 + 1,
+        }
+    }
+
+    // All seven pieces in random order, so each of them comes up once per bag.
+    pub fn new_bag(rng: &mut impl Rng) -> Vec<TetrisPieceType> {
+        let mut bag = vec![
+            TetrisPieceType::I,
+            TetrisPieceType::O,
+            TetrisPieceType::T,
+            TetrisPieceType::S,
+            TetrisPieceType::Z,
+            TetrisPieceType::J,
+            TetrisPieceType::L,
+        ];
+        bag.shuffle(rng);
+        bag
+    }
+
+    pub fn from_letter(letter: char) -> Option<TetrisPieceType> {
+        match letter.to_ascii_uppercase() {
+            'I' => Some(TetrisPieceType::I),
+            'O' => Some(TetrisPieceType::O),
+            'T' => Some(TetrisPieceType::T),
+            'S' => Some(TetrisPieceType::S),
+            'Z' => Some(TetrisPieceType::Z),
+            'J' => Some(TetrisPieceType::J),
+            'L' => Some(TetrisPieceType::L),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
 pub enum TetrisPieceState {
     Active,
     Placing,
     Placed,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
 pub struct TetrisPiece {
     pub piece_type: TetrisPieceType,
-    pub grid: Vec<i32>, // 2D vector to represent the shape in its current rotation
-    pub x: i32,         // x position of the piece
-    pub y: i32,         // y position of the piece
-    pub rotation: u8,   // rotation of the piece (0-3)
-    pub kick_table: HashMap<u8, Vec<(i32, i32)>>,
+    pub x: i32,       // x position of the piece
+    pub y: i32,       // y position of the piece
+    pub rotation: u8, // rotation of the piece (0-3)
     pub state: TetrisPieceState,
 }
 
+// Spawn orientation of every piece in a 5x5 box, indexed as x + y * 5.
+const I_SPAWN: [u8; 25] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+];
+const O_SPAWN: [u8; 25] = [
+    0, 0, 0, 0, 0, 0, 0, 1, 1, 0, 0, 0, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+];
+const T_SPAWN: [u8; 25] = [
+    0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+];
+const S_SPAWN: [u8; 25] = [
+    0, 0, 0, 0, 0, 0, 0, 1, 1, 0, 0, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+];
+const Z_SPAWN: [u8; 25] = [
+    0, 0, 0, 0, 0, 0, 1, 1, 0, 0, 0, 0, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+];
+const J_SPAWN: [u8; 25] = [
+    0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+];
+const L_SPAWN: [u8; 25] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+];
+
+const fn mask_from_grid(grid: &[u8; 25]) -> u32 {
+    let mut mask = 0;
+    let mut i = 0;
+    while i < 25 {
+        if grid[i] != 0 {
+            mask |= 1 << i;
+        }
+        i += 1;
+    }
+    mask
+}
+
+const fn rotate_mask_cw(mask: u32) -> u32 {
+    let mut rotated = 0;
+    let mut y = 0;
+    while y < 5 {
+        let mut x = 0;
+        while x < 5 {
+            if mask & (1 << (x + y * 5)) != 0 {
+                rotated |= 1 << (4 - y + x * 5);
+            }
+            x += 1;
+        }
+        y += 1;
+    }
+    rotated
+}
+
+const fn rotations(spawn: &[u8; 25]) -> [u32; 4] {
+    let r0 = mask_from_grid(spawn);
+    let r1 = rotate_mask_cw(r0);
+    let r2 = rotate_mask_cw(r1);
+    let r3 = rotate_mask_cw(r2);
+    [r0, r1, r2, r3]
+}
+
+// Occupied cells of every piece type in each rotation, one bit per cell of the 5x5 box.
+static SHAPES: [[u32; 4]; 8] = [
+    rotations(&I_SPAWN),
+    rotations(&O_SPAWN),
+    rotations(&T_SPAWN),
+    rotations(&S_SPAWN),
+    rotations(&Z_SPAWN),
+    rotations(&J_SPAWN),
+    rotations(&L_SPAWN),
+    [0; 4],
+];
+
+// SRS offset tables, kick vectors are the difference between two rotation states.
+static JLSTZ_OFFSETS: [[(i32, i32); 5]; 4] = [
+    [(0, 0), (0, 0), (0, 0), (0, 0), (0, 0)],
+    [(0, 0), (1, 0), (1, -1), (0, 2), (1, 2)],
+    [(0, 0), (0, 0), (0, 0), (0, 0), (0, 0)],
+    [(0, 0), (-1, 0), (-1, -1), (0, 2), (-1, 2)],
+];
+static O_OFFSETS: [[(i32, i32); 1]; 4] = [[(0, 0)], [(0, -1)], [(-1, -1)], [(-1, 0)]];
+static I_OFFSETS: [[(i32, i32); 5]; 4] = [
+    [(0, 0), (-1, 0), (2, 0), (-1, 0), (2, 0)],
+    [(-1, 0), (0, 0), (0, 0), (0, 1), (0, -2)],
+    [(-1, 1), (1, 1), (-2, 1), (1, 0), (-2, 0)],
+    [(0, 1), (0, 1), (0, 1), (0, -1), (0, 2)],
+];
+
 impl TetrisPiece {
     pub fn new(piece_type: TetrisPieceType) -> TetrisPiece {
-        let grid = match piece_type {
-            TetrisPieceType::I => vec![
-                0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
-            ],
-            TetrisPieceType::O => vec![
-                0, 0, 0, 0, 0, 0, 0, 2, 2, 0, 0, 0, 2, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
-            ],
-            TetrisPieceType::T => vec![
-                0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 3, 3, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
-            ],
-            TetrisPieceType::S => vec![
-                0, 0, 0, 0, 0, 0, 0, 4, 4, 0, 0, 4, 4, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
-            ],
-            TetrisPieceType::Z => vec![
-                0, 0, 0, 0, 0, 0, 5, 5, 0, 0, 0, 0, 5, 5, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
-            ],
-            TetrisPieceType::J => vec![
-                0, 0, 0, 0, 0, 0, 6, 0, 0, 0, 0, 6, 6, 6, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
-            ],
-            TetrisPieceType::L => vec![
-                0, 0, 0, 0, 0, 0, 0, 0, 7, 0, 0, 7, 7, 7, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
-            ],
-            TetrisPieceType::None => vec![0; 25],
-        };
-        let kick_table = match piece_type {
-            TetrisPieceType::T
-            | TetrisPieceType::S
-            | TetrisPieceType::Z
-            | TetrisPieceType::J
-            | TetrisPieceType::L => {
-                let mut kick_table: HashMap<u8, Vec<(i32, i32)>> = HashMap::new();
-                kick_table.insert(0, vec![(0, 0), (0, 0), (0, 0), (0, 0), (0, 0)]);
-                kick_table.insert(1, vec![(0, 0), (1, 0), (1, -1), (0, 2), (1, 2)]);
-                kick_table.insert(2, vec![(0, 0), (0, 0), (0, 0), (0, 0), (0, 0)]);
-                kick_table.insert(3, vec![(0, 0), (-1, 0), (-1, -1), (0, 2), (-1, 2)]);
-                kick_table
-            }
-            TetrisPieceType::O => {
-                let mut kick_table: HashMap<u8, Vec<(i32, i32)>> = HashMap::new();
-                kick_table.insert(0, vec![(0, 0)]);
-                kick_table.insert(1, vec![(0, -1)]);
-                kick_table.insert(2, vec![(-1, -1)]);
-                kick_table.insert(3, vec![(-1, 0)]);
-                kick_table
-            }
-            TetrisPieceType::I => {
-                let mut kick_table: HashMap<u8, Vec<(i32, i32)>> = HashMap::new();
-                kick_table.insert(0, vec![(0, 0), (-1, 0), (2, 0), (-1, 0), (2, 0)]);
-                kick_table.insert(1, vec![(-1, 0), (0, 0), (0, 0), (0, 1), (0, -2)]);
-                kick_table.insert(2, vec![(-1, 1), (1, 1), (-2, 1), (1, 0), (-2, 0)]);
-                kick_table.insert(3, vec![(0, 1), (0, 1), (0, 1), (0, -1), (0, 2)]);
-                kick_table
-            }
-            TetrisPieceType::None => HashMap::new(),
-        };
         TetrisPiece {
             piece_type,
-            grid,
             x: 2,
             y: 0,
             rotation: 0,
-            kick_table,
             state: TetrisPieceState::Active,
         }
     }
 
-    fn gen_kick_vectors(&self, prev_rotation: u8, next_rotation: u8) -> Vec<(i32, i32)> {
-        let mut kick_vectors: Vec<(i32, i32)> = Vec::new();
-        let prev_kick_vectors: Vec<(i32, i32)> = self.kick_table[&prev_rotation].clone();
-        let next_kick_vectors: Vec<(i32, i32)> = self.kick_table[&next_rotation].clone();
+    // Resets the piece to its spawn orientation and position on the board.
+    pub fn spawn(&mut self, game_board: &GameBoard) {
+        (self.x, self.y) = game_board.spawn_position();
+        self.rotation = 0;
+        self.state = TetrisPieceState::Active;
+    }
 
-        prev_kick_vectors
+    // Bitmask of the occupied cells in the 5x5 box for the current rotation, bit x + y * 5.
+    pub fn mask(&self) -> u32 {
+        SHAPES[self.piece_type as usize][self.rotation as usize]
+    }
+
+    // Occupied cells of row y of the 5x5 box, bit x set for column x.
+    pub fn row_mask(&self, y: i32) -> u32 {
+        (self.mask() >> (y * 5)) & 0x1f
+    }
+
+    // Offsets of the occupied cells inside the 5x5 box.
+    pub fn cells(&self) -> impl Iterator<Item = (i32, i32)> {
+        let mask = self.mask();
+        (0..25)
+            .filter(move |i| mask & (1 << i) != 0)
+            .map(|i| (i % 5, i / 5))
+    }
+
+    pub fn grid(&self) -> [i32; 25] {
+        let mut grid = [0; 25];
+        for (x, y) in self.cells() {
+            grid[(x + y * 5) as usize] = self.piece_type.id();
+        }
+        grid
+    }
+
+    fn offsets(&self, rotation: u8) -> &'static [(i32, i32)] {
+        match self.piece_type {
+            TetrisPieceType::T
+            | TetrisPieceType::S
+            | TetrisPieceType::Z
+            | TetrisPieceType::J
+            | TetrisPieceType::L => &JLSTZ_OFFSETS[rotation as usize],
+            TetrisPieceType::O => &O_OFFSETS[rotation as usize],
+            TetrisPieceType::I => &I_OFFSETS[rotation as usize],
+            TetrisPieceType::None => &[],
+        }
+    }
+
+    fn kick_vectors(
+        &self,
+        prev_rotation: u8,
+        next_rotation: u8,
+    ) -> impl Iterator<Item = (i32, i32)> {
+        self.offsets(prev_rotation)
             .iter()
-            .zip(next_kick_vectors.iter())
-            .for_each(|(prev, next)| {
-                kick_vectors.push((prev.0 - next.0, prev.1 - next.1));
-            });
-        kick_vectors
+            .zip(self.offsets(next_rotation).iter())
+            .map(|(prev, next)| (prev.0 - next.0, prev.1 - next.1))
     }
 
-    pub fn new_random_piece() -> TetrisPiece {
-        let mut rng = rand::thread_rng();
+    pub fn new_random_piece(rng: &mut impl Rng) -> TetrisPiece {
         let piece_type: TetrisPieceType = match rng.gen_range(0..=6) {
             0 => TetrisPieceType::I,
             1 => TetrisPieceType::O,
@@ -135,19 +245,18 @@ impl TetrisPiece {
         if self.state == TetrisPieceState::Placed {
             return;
         }
-        self.x = self.x + dx;
-        self.y = self.y + dy;
-        if game_board.test_placement(self) {
-            return;
-        } else {
-            self.x = self.x - dx;
-            self.y = self.y - dy;
+        self.x += dx;
+        self.y += dy;
+        if !game_board.test_placement(self) {
+            self.x -= dx;
+            self.y -= dy;
         }
     }
 
-    pub fn srs_rotate(&mut self, game_board: &GameBoard, cw_rotation: bool) {
+    // Rotates with SRS wall kicks, returns the index of the kick that made the rotation fit.
+    pub fn srs_rotate(&mut self, game_board: &GameBoard, cw_rotation: bool) -> Option<usize> {
         if self.state == TetrisPieceState::Placed {
-            return;
+            return None;
         }
         let prev_rotation = self.rotation;
         let prev_x = self.x;
@@ -158,113 +267,219 @@ impl TetrisPiece {
         } else {
             self.rotate_ccw();
         }
-        let kick_vectors = self.gen_kick_vectors(prev_rotation, self.rotation);
-        for kick_vector in kick_vectors {
-            self.x = self.x + kick_vector.0;
-            self.y = self.y - kick_vector.1;
+        for (i, kick_vector) in self.kick_vectors(prev_rotation, self.rotation).enumerate() {
+            self.x += kick_vector.0;
+            self.y -= kick_vector.1;
             if game_board.test_placement(self) {
-                return;
+                return Some(i);
             }
             self.x = prev_x;
             self.y = prev_y;
         }
 
-        if cw_rotation {
-            self.rotate_ccw();
-        } else {
-            self.rotate_cw();
-        }
+        self.rotation = prev_rotation;
+        None
     }
 
     pub fn rotate_ccw(&mut self) {
         self.rotation = (self.rotation + 3) % 4;
-        let mut tmp_grid: Vec<i32> = vec![0; self.grid.len()];
-
-        for x in 0..5 {
-            for y in 0..5 {
-                tmp_grid[(x + y * 5) as usize] = self.grid[(4 - y + x * 5) as usize];
-            }
-        }
-        self.grid = tmp_grid;
     }
 
     pub fn rotate_cw(&mut self) {
         self.rotation = (self.rotation + 1) % 4;
-        let mut tmp_grid: Vec<i32> = vec![0; self.grid.len()];
-
-        for x in 0..5 {
-            for y in 0..5 {
-                tmp_grid[(4 - y + x * 5) as usize] = self.grid[(x + y * 5) as usize];
-            }
-        }
-        self.grid = tmp_grid;
     }
 }
 
+// A piece is drawn as its 5x5 box in the text form of a board.
 impl fmt::Display for TetrisPiece {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        self.grid.iter().enumerate().for_each(|(i, x)| {
-            if i % 5_usize == 0 {
-                writeln!(f).unwrap();
+        for row in self.grid().chunks(5) {
+            let line: String = row.iter().map(|id| block_char(*id)).collect();
+            writeln!(f, "{}", line)?;
+        }
+        Ok(())
+    }
+}
+
+// Reads a piece back from a drawing of it, its own box or a whole board with nothing else
+// on it. The piece takes the first rotation that fits and is moved to where it is drawn.
+impl FromStr for TetrisPiece {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<TetrisPiece, String> {
+        let drawing: GameBoard = text.parse()?;
+        let mut id = 0;
+        let mut cells = Vec::new();
+        for y in 0..drawing.height {
+            for x in 0..drawing.width {
+                match drawing.cell(x, y) {
+                    0 => {}
+                    cell if id == 0 || cell == id => {
+                        id = cell;
+                        cells.push((x, y));
+                    }
+                    _ => return Err("drawing has more than one piece".to_string()),
+                }
             }
-            write!(f, "{}", x).unwrap();
-        });
-        writeln!(f, "\n{}", self.rotation).unwrap();
-        fmt::Result::Ok(())
+        }
+        let piece_type =
+            TetrisPieceType::from_letter(block_char(id)).ok_or("drawing has no piece")?;
+        let mut piece = TetrisPiece::new(piece_type);
+        for rotation in 0..4 {
+            piece.rotation = rotation;
+            let (x, y) = piece.cells().next().unwrap();
+            (piece.x, piece.y) = (cells[0].0 - x, cells[0].1 - y);
+            if piece
+                .cells()
+                .map(|(x, y)| (piece.x + x, piece.y + y))
+                .eq(cells.iter().copied())
+            {
+                return Ok(piece);
+            }
+        }
+        Err(format!("drawing is not a {:?} piece", piece_type))
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::rng::GameRng;
+
+    #[test]
+    fn test_new_bag() {
+        let mut rng = GameRng::new(7);
+        for _ in 0..10 {
+            let mut bag = TetrisPieceType::new_bag(&mut rng);
+            bag.sort_by_key(|piece_type| piece_type.id());
+            bag.dedup();
+            assert_eq!(bag.len(), 7);
+        }
+    }
 
     #[test]
     fn test_piece_creation() {
         let t: TetrisPiece = TetrisPiece::new(TetrisPieceType::T);
         assert_eq!(t.piece_type, TetrisPieceType::T);
         assert_eq!(
-            t.grid,
-            vec![0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 3, 3, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,]
+            t.to_string(),
+            "\
+.....
+..T..
+.TTT.
+.....
+.....
+"
+        );
+    }
+
+    #[test]
+    fn test_rotation_states() {
+        let mut t: TetrisPiece = TetrisPiece::new(TetrisPieceType::L);
+        let spawn = t;
+        t.rotate_cw();
+        assert_eq!(
+            t.to_string(),
+            "\
+.....
+..L..
+..L..
+..LL.
+.....
+"
         );
+        t.rotate_ccw();
+        assert_eq!(t, spawn);
+        for _ in 0..4 {
+            t.rotate_ccw();
+        }
+        assert_eq!(t.mask(), spawn.mask());
+        assert_eq!(t.cells().count(), 4);
+    }
+
+    #[test]
+    fn test_parse_piece() {
+        let mut l = TetrisPiece::new(TetrisPieceType::L);
+        l.rotate_cw();
+        let parsed: TetrisPiece = l.to_string().parse().unwrap();
+        assert_eq!(
+            (parsed.piece_type, parsed.rotation),
+            (TetrisPieceType::L, 1)
+        );
+        assert_eq!((parsed.x, parsed.y), (0, 0));
+
+        let s: TetrisPiece = "
+            ..........
+            ......SS..
+            .....SS...
+        "
+        .parse()
+        .unwrap();
+        assert_eq!((s.piece_type, s.rotation), (TetrisPieceType::S, 0));
+        assert_eq!((s.x, s.y), (4, 0));
+
+        assert!("..T\n.TT\n..T".parse::<TetrisPiece>().is_ok());
+        assert!("..T\nTTT\n.T.".parse::<TetrisPiece>().is_err());
+        assert!("..T\n.JT\n..T".parse::<TetrisPiece>().is_err());
+        assert!("...\nXXX".parse::<TetrisPiece>().is_err());
     }
 
     #[test]
     fn test_srs_rotation() {
-        let mut b = GameBoard::new(10, 20);
         let mut t = TetrisPiece::new(TetrisPieceType::J);
         t.x = 2;
         t.y = 13;
 
-        b.grid = vec![
-            0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
-            0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
-            0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
-            0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
-            0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0,
-            1, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 0, 1, 1, 1, 0, 0, 0, 1, 1, 1, 1, 1, 0, 0,
-            0, 0, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0, 1, 1, 1, 1,
-        ];
+        let mut b: GameBoard = "
+            ..........
+            ..........
+            ..........
+            ..........
+            ..........
+            ..........
+            ..........
+            ..........
+            ..........
+            ..........
+            ..........
+            ..........
+            ..........
+            ....XX....
+            .....XXX..
+            ......XXXX
+            .XXX...XXX
+            XX....XXXX
+            XXXX..XXXX
+            XXXXX.XXXX
+        "
+        .parse()
+        .unwrap();
         t.srs_rotate(&b, false);
         b.place_piece(&t);
-        b.grid.iter().enumerate().for_each(|(i, x)| {
-            if i % 10 == 0 {
-                println!();
-            }
-            print!("{}", x);
-        });
-        println!("\n\n\n");
         assert_eq!(
-            b.grid,
-            vec![
-                0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
-                0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
-                0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
-                0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
-                0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 0, 0, 0, 0,
-                0, 0, 0, 0, 0, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 0, 1, 1, 1, 0, 6, 0, 1,
-                1, 1, 1, 1, 0, 0, 0, 6, 1, 1, 1, 1, 1, 1, 1, 1, 6, 6, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0,
-                1, 1, 1, 1,
-            ]
+            b.to_string(),
+            "\
+..........
+..........
+..........
+..........
+..........
+..........
+..........
+..........
+..........
+..........
+..........
+..........
+..........
+....XX....
+.....XXX..
+......XXXX
+.XXX.J.XXX
+XX...JXXXX
+XXXXJJXXXX
+XXXXX.XXXX
+"
         );
     }
 }
@@ -0,0 +1,74 @@
+use crate::tetris_game::TICKS_PER_SECOND;
+
+// Delays between the moments of a piece's life, all counted in engine ticks.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Timing {
+    pub are: u32,        // from a lock that cleared nothing to the next piece spawning
+    pub line_clear: u32, // cleared rows stay on screen this long
+    pub line_are: u32,   // from the end of a line clear to the next piece spawning
+    pub das: u32,        // holding a direction this long starts auto shift
+    pub arr: u32,        // between two moves of auto shift
+    pub lock_delay: u32, // a piece resting on the stack locks after this long
+}
+
+pub const DEFAULT_TIMING: Timing = Timing {
+    are: 0,
+    line_clear: 0,
+    line_are: 0,
+    das: 34,
+    arr: 10,
+    lock_delay: 100,
+};
+
+// Converts a delay given in frames at 60 Hz, the unit timing tables are usually written in.
+const fn frames(frames: u32) -> u32 {
+    frames * TICKS_PER_SECOND / 60
+}
+
+const fn master(are: u32, line_are: u32, das: u32, lock_delay: u32, line_clear: u32) -> Timing {
+    Timing {
+        are: frames(are),
+        line_clear: frames(line_clear),
+        line_are: frames(line_are),
+        das: frames(das),
+        arr: frames(1),
+        lock_delay: frames(lock_delay),
+    }
+}
+
+// Master mode speeds up every section of 100 levels, each entry applies from its level on.
+// The delays follow the Tetris The Grand Master 3 Master mode table.
+pub const MASTER_TIMINGS: [(u32, Timing); 6] = [
+    (0, master(25, 25, 14, 30, 40)),
+    (500, master(25, 25, 8, 30, 25)),
+    (600, master(25, 16, 8, 30, 16)),
+    (700, master(16, 12, 8, 30, 12)),
+    (800, master(12, 6, 8, 30, 6)),
+    (900, master(12, 6, 6, 17, 6)),
+];
+
+// Looks up the value of the last entry of a (threshold, value) table that the key has reached.
+pub fn by_threshold<T: Copy>(table: &[(u32, T)], key: u32) -> T {
+    table
+        .iter()
+        .take_while(|(from, _)| *from <= key)
+        .last()
+        .unwrap_or(&table[0])
+        .1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game_mode::MASTER_LEVELS;
+
+    #[test]
+    fn test_master_timings() {
+        assert_eq!(by_threshold(&MASTER_TIMINGS, 0).das, frames(14));
+        assert_eq!(by_threshold(&MASTER_TIMINGS, 499).das, frames(14));
+        assert_eq!(by_threshold(&MASTER_TIMINGS, 500).das, frames(8));
+        assert_eq!(by_threshold(&MASTER_TIMINGS, 950).lock_delay, frames(17));
+        assert_eq!(by_threshold(&MASTER_TIMINGS, MASTER_LEVELS).are, frames(12));
+        assert_eq!(frames(60), TICKS_PER_SECOND);
+    }
+}
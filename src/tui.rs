@@ -0,0 +1,544 @@
+use crossterm::cursor::{Hide, MoveTo, Show};
+use crossterm::event::{
+    self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers, KeyboardEnhancementFlags,
+    PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
+};
+use crossterm::style::{Color, Print, ResetColor, SetBackgroundColor, SetForegroundColor};
+use crossterm::terminal::{
+    self, disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen,
+};
+use crossterm::{execute, queue};
+use std::io::{self, BufWriter, Stdout, Write};
+use std::time::{Duration, Instant};
+
+use crate::frontend::{
+    key_command, key_input, shift_direction, Command, GameView, InputSource, Key, Renderer, Session,
+};
+use crate::game_board::GARBAGE_ID;
+use crate::tetris_game::{Input, TetrisGame};
+use crate::tetris_piece::{TetrisPiece, TetrisPieceType};
+
+// Terminals without key-up events repeat a held key only after their own delay, so a
+// direction counts as released once its key hasn't come again for a while. Right after
+// the press that is TAP_MS, shorter than DAS so that a tap moves the piece once, and once
+// the key repeats it is REPEAT_MS, longer than the gap between two repeats.
+const TAP_MS: u64 = 150;
+const REPEAT_MS: u64 = 100;
+
+// Columns of the panels on either side of the board.
+const PANEL_WIDTH: i32 = 14;
+
+const FRAME_MS: u64 = 16;
+
+fn block_color(id: i32) -> Color {
+    match id {
+        1 => Color::Cyan,
+        2 => Color::Yellow,
+        3 => Color::Magenta,
+        4 => Color::Green,
+        5 => Color::Red,
+        6 => Color::Blue,
+        7 => Color::DarkYellow,
+        GARBAGE_ID => Color::Grey,
+        _ => Color::Reset,
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct Cell {
+    ch: char,
+    fg: Color,
+    bg: Color,
+}
+
+const BLANK: Cell = Cell {
+    ch: ' ',
+    fg: Color::Reset,
+    bg: Color::Reset,
+};
+
+// A frame of text, drawn in full and then written to the terminal in one go.
+struct Screen {
+    width: i32,
+    height: i32,
+    cells: Vec<Cell>,
+}
+
+impl Screen {
+    fn new(width: i32, height: i32) -> Screen {
+        Screen {
+            width,
+            height,
+            cells: vec![BLANK; (width * height) as usize],
+        }
+    }
+
+    fn set(&mut self, x: i32, y: i32, cell: Cell) {
+        if x >= 0 && x < self.width && y >= 0 && y < self.height {
+            self.cells[(x + y * self.width) as usize] = cell;
+        }
+    }
+
+    fn text(&mut self, x: i32, y: i32, text: &str) {
+        for (i, ch) in text.chars().enumerate() {
+            self.set(x + i as i32, y, Cell { ch, ..BLANK });
+        }
+    }
+
+    // Draws a grid of blocks two rows to a line with half blocks, the upper one in the
+    // foreground color and the lower one in the background color.
+    fn blocks(
+        &mut self,
+        x: i32,
+        y: i32,
+        width: i32,
+        height: i32,
+        color: impl Fn(i32, i32) -> Option<Color>,
+    ) {
+        for row in 0..(height + 1) / 2 {
+            for column in 0..width {
+                let lower = if row * 2 + 1 < height {
+                    color(column, row * 2 + 1)
+                } else {
+                    None
+                };
+                let cell = match (color(column, row * 2), lower) {
+                    (None, None) => BLANK,
+                    (Some(upper), None) => Cell {
+                        ch: '▀',
+                        fg: upper,
+                        bg: Color::Reset,
+                    },
+                    (None, Some(lower)) => Cell {
+                        ch: '▄',
+                        fg: lower,
+                        bg: Color::Reset,
+                    },
+                    (Some(upper), Some(lower)) => Cell {
+                        ch: '▀',
+                        fg: upper,
+                        bg: lower,
+                    },
+                };
+                self.set(x + column, y + row, cell);
+            }
+        }
+    }
+
+    // A piece in the hold or next panel, its cells fit in 4 columns and one line.
+    fn preview(&mut self, x: i32, y: i32, piece: &TetrisPiece) {
+        if piece.piece_type == TetrisPieceType::None {
+            return;
+        }
+        let left = piece.cells().map(|(x, _)| x).min().unwrap_or(0);
+        let top = piece.cells().map(|(_, y)| y).min().unwrap_or(0);
+        let color = block_color(piece.piece_type.id());
+        self.blocks(x, y, 4, 2, |column, row| {
+            piece
+                .cells()
+                .any(|cell| cell == (column + left, row + top))
+                .then_some(color)
+        });
+    }
+
+    fn write(&self, out: &mut impl Write) -> io::Result<()> {
+        let mut colors = (Color::Reset, Color::Reset);
+        queue!(out, ResetColor)?;
+        for y in 0..self.height {
+            queue!(out, MoveTo(0, y as u16))?;
+            for x in 0..self.width {
+                let cell = self.cells[(x + y * self.width) as usize];
+                if (cell.fg, cell.bg) != colors {
+                    colors = (cell.fg, cell.bg);
+                    queue!(out, ResetColor)?;
+                    if cell.fg != Color::Reset {
+                        queue!(out, SetForegroundColor(cell.fg))?;
+                    }
+                    if cell.bg != Color::Reset {
+                        queue!(out, SetBackgroundColor(cell.bg))?;
+                    }
+                }
+                queue!(out, Print(cell.ch))?;
+            }
+        }
+        queue!(out, ResetColor)?;
+        out.flush()
+    }
+}
+
+// Lays out the game like the window: hold and stats to the left of the board, next and
+// the score to the right.
+fn draw(game: &TetrisGame, stats: &[String]) -> Screen {
+    let board = &game.board;
+    let rows = board.visible_height();
+    let board_x = PANEL_WIDTH + 1;
+    let right_x = board_x + board.width + 2;
+    let mut screen = Screen::new(right_x + PANEL_WIDTH, (rows + 1) / 2 + 2);
+
+    let bottom = (rows + 1) / 2 + 1;
+    screen.text(
+        board_x - 1,
+        0,
+        &format!("┌{}┐", "─".repeat(board.width as usize)),
+    );
+    screen.text(
+        board_x - 1,
+        bottom,
+        &format!("└{}┘", "─".repeat(board.width as usize)),
+    );
+    for y in 1..bottom {
+        screen.text(board_x - 1, y, "│");
+        screen.text(board_x + board.width, y, "│");
+    }
+
+    let playing = !game.is_spawning() && game.game_over.is_none();
+    let ghost = game.ghost();
+    let covers = |piece: &TetrisPiece, x: i32, y: i32| {
+        piece
+            .cells()
+            .any(|(cell_x, cell_y)| (piece.x + cell_x, piece.y + cell_y) == (x, y))
+    };
+    screen.blocks(board_x, 1, board.width, rows, |x, row| {
+        let y = row + board.hidden_rows;
+        if playing && covers(&game.piece, x, y) {
+            return Some(block_color(game.piece.piece_type.id()));
+        }
+        let id = board.cell(x, y);
+        if id > 0 && game.block_alpha(x, y) > 0 {
+            return Some(block_color(id));
+        }
+        (playing && covers(&ghost, x, y)).then_some(Color::DarkGrey)
+    });
+
+    screen.text(1, 0, "HOLD");
+    screen.preview(1, 1, &game.hold);
+    for (i, line) in stats.iter().enumerate() {
+        screen.text(1, 3 + i as i32, line);
+    }
+    screen.text(right_x, 0, "NEXT");
+    screen.preview(right_x, 1, &game.next);
+    screen.text(right_x, 3, "SCORE");
+    screen.text(right_x, 4, &game.score.to_string());
+    if let Some(reason) = game.game_over {
+        screen.text(right_x, 6, "GAME OVER");
+        screen.text(right_x, 7, &reason.to_string().to_uppercase());
+        screen.text(right_x, 9, "ESC TO QUIT");
+    }
+    screen
+}
+
+// Turns the left and right keys into shift inputs. With key-up events from the terminal
+// a direction is held until its key is let go, without them it is let go by timeout.
+struct ShiftKeys {
+    key_up_events: bool,
+    held: Option<HeldKey>,
+}
+
+struct HeldKey {
+    direction: i32,
+    last_seen: Instant,
+    repeating: bool,
+}
+
+impl ShiftKeys {
+    fn key(&mut self, direction: i32, kind: KeyEventKind, now: Instant) -> Option<Input> {
+        match (kind, &mut self.held) {
+            (KeyEventKind::Release, Some(held)) if held.direction == direction => {
+                self.held = None;
+                Some(Input::Shift(0))
+            }
+            (KeyEventKind::Release, _) => None,
+            (_, Some(held)) if held.direction == direction => {
+                held.last_seen = now;
+                held.repeating = true;
+                None
+            }
+            _ => {
+                self.held = Some(HeldKey {
+                    direction,
+                    last_seen: now,
+                    repeating: false,
+                });
+                Some(Input::Shift(direction))
+            }
+        }
+    }
+
+    // Lets go of a direction whose key stopped repeating, for terminals without key-up.
+    fn update(&mut self, now: Instant) -> Option<Input> {
+        let held = self.held.as_ref()?;
+        let timeout = if held.repeating { REPEAT_MS } else { TAP_MS };
+        if self.key_up_events || now - held.last_seen < Duration::from_millis(timeout) {
+            return None;
+        }
+        self.held = None;
+        Some(Input::Shift(0))
+    }
+}
+
+// Raw mode and the alternate screen for as long as the game runs, restored when dropped
+// so that an error doesn't leave the terminal broken.
+struct Terminal {
+    out: BufWriter<Stdout>,
+    key_up_events: bool,
+}
+
+impl Terminal {
+    fn enter() -> io::Result<Terminal> {
+        enable_raw_mode()?;
+        let mut out = BufWriter::new(io::stdout());
+        execute!(out, EnterAlternateScreen, Hide)?;
+        let key_up_events = terminal::supports_keyboard_enhancement().unwrap_or(false);
+        if key_up_events {
+            execute!(
+                out,
+                PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::REPORT_EVENT_TYPES)
+            )?;
+        }
+        Ok(Terminal { out, key_up_events })
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        if self.key_up_events {
+            let _ = execute!(self.out, PopKeyboardEnhancementFlags);
+        }
+        let _ = execute!(self.out, Show, LeaveAlternateScreen);
+        let _ = disable_raw_mode();
+    }
+}
+
+// Draws the game into the terminal. Messages would garble the screen while it is up, so
+// they are kept and printed once the terminal is restored.
+struct TuiRenderer {
+    terminal: Terminal,
+    messages: Vec<String>,
+}
+
+impl Renderer for TuiRenderer {
+    fn render(&mut self, view: &GameView) -> Result<(), String> {
+        draw(view.game, view.stats)
+            .write(&mut self.terminal.out)
+            .map_err(|e| e.to_string())
+    }
+
+    fn message(&mut self, text: &str) {
+        self.messages.push(text.to_string());
+    }
+
+    fn frame_time(&self) -> Duration {
+        Duration::from_millis(FRAME_MS)
+    }
+}
+
+struct TuiInput {
+    shift_keys: ShiftKeys,
+}
+
+impl InputSource for TuiInput {
+    fn poll(&mut self, game: &TetrisGame) -> Result<Vec<Command>, String> {
+        let mut commands = Vec::new();
+        while event::poll(Duration::ZERO).map_err(|e| e.to_string())? {
+            let Event::Key(key) = event::read().map_err(|e| e.to_string())? else {
+                continue;
+            };
+            if is_interrupt(&key) {
+                commands.push(Command::Quit);
+                continue;
+            }
+            let Some(k) = tui_key(key.code) else {
+                continue;
+            };
+            let pressed = key.kind != KeyEventKind::Release;
+            let command = match shift_direction(k) {
+                Some(direction) => self
+                    .shift_keys
+                    .key(direction, key.kind, Instant::now())
+                    .map(Command::Input),
+                None if pressed => {
+                    key_command(k, false).or_else(|| key_input(k, game).map(Command::Input))
+                }
+                None => None,
+            };
+            commands.extend(command);
+        }
+        commands.extend(self.shift_keys.update(Instant::now()).map(Command::Input));
+        Ok(commands)
+    }
+}
+
+// Ctrl-C quits like Esc, raw mode keeps it from interrupting the game.
+fn is_interrupt(key: &KeyEvent) -> bool {
+    key.code == KeyCode::Char('c')
+        && key.modifiers.contains(KeyModifiers::CONTROL)
+        && key.kind != KeyEventKind::Release
+}
+
+// The frontend's name for a key, letters count the same with shift held.
+fn tui_key(code: KeyCode) -> Option<Key> {
+    let key = match code {
+        KeyCode::Left => Key::Left,
+        KeyCode::Right => Key::Right,
+        KeyCode::Down => Key::Down,
+        KeyCode::Esc => Key::Escape,
+        KeyCode::F(12) => Key::F12,
+        KeyCode::Char(c) => Key::Char(c.to_ascii_lowercase()),
+        _ => return None,
+    };
+    Some(key)
+}
+
+// Plays the game in the terminal until it ends and a key is pressed, or until Esc quits
+// it early. Inputs are recorded like in the window.
+pub fn play(session: &mut Session) -> Result<(), String> {
+    let terminal = Terminal::enter().map_err(|e| e.to_string())?;
+    let mut input = TuiInput {
+        shift_keys: ShiftKeys {
+            key_up_events: terminal.key_up_events,
+            held: None,
+        },
+    };
+    let mut renderer = TuiRenderer {
+        terminal,
+        messages: Vec::new(),
+    };
+    let result = session.run(&mut input, &mut renderer).and_then(|()| {
+        if session.game.game_over.is_none() {
+            return Ok(());
+        }
+        // Leave the final board up until a key is pressed.
+        let stats = session.stats();
+        renderer.render(&session.view(&stats))?;
+        loop {
+            if let Event::Key(key) = event::read().map_err(|e| e.to_string())? {
+                if key.kind != KeyEventKind::Release {
+                    return Ok(());
+                }
+            }
+        }
+    });
+    drop(renderer.terminal);
+    for message in renderer.messages {
+        println!("{}", message);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game_board::GameBoard;
+    use crate::game_mode::GameMode;
+
+    fn line(screen: &Screen, y: i32) -> String {
+        (0..screen.width)
+            .map(|x| screen.cells[(x + y * screen.width) as usize].ch)
+            .collect()
+    }
+
+    #[test]
+    fn test_draw() {
+        let mut board = GameBoard::with_hidden_rows(4, 10, 2);
+        board.set_cell(0, 11, 1);
+        board.set_cell(1, 10, 8);
+        board.set_cell(1, 11, 8);
+        let mut game = TetrisGame::new(board, GameMode::Endless, 3);
+        for _ in 0..100 {
+            game.tick();
+        }
+        let screen = draw(&game, &["LINES 0".to_string()]);
+        assert_eq!(screen.height, 7);
+        assert!(line(&screen, 0).contains("HOLD"));
+        assert!(line(&screen, 0).contains("┌────┐"));
+        assert!(line(&screen, 3).contains("LINES 0"));
+        assert!(line(&screen, 6).contains("└────┘"));
+
+        // The bottom line holds rows 10 and 11 of the board: an I block in the lower half
+        // of the first column, garbage in both halves of the second.
+        let cell = |x: i32| screen.cells[(PANEL_WIDTH + 1 + x + 5 * screen.width) as usize];
+        assert_eq!(
+            cell(0),
+            Cell {
+                ch: '▄',
+                fg: Color::Cyan,
+                bg: Color::Reset
+            }
+        );
+        assert_eq!(
+            cell(1),
+            Cell {
+                ch: '▀',
+                fg: Color::Grey,
+                bg: Color::Grey
+            }
+        );
+        // The ghost of the falling piece shows somewhere on the board.
+        assert!(screen.cells.iter().any(|cell| cell.fg == Color::DarkGrey));
+    }
+
+    #[test]
+    fn test_keys() {
+        assert_eq!(tui_key(KeyCode::Char('D')), Some(Key::Char('d')));
+        assert_eq!(tui_key(KeyCode::Esc), Some(Key::Escape));
+        assert_eq!(tui_key(KeyCode::Tab), None);
+        let ctrl_c = KeyEvent::new(KeyCode::Char('c'), KeyModifiers::CONTROL);
+        assert!(is_interrupt(&ctrl_c));
+        assert!(!is_interrupt(&KeyEvent::new(
+            KeyCode::Char('c'),
+            KeyModifiers::NONE
+        )));
+    }
+
+    #[test]
+    fn test_shift_without_key_up() {
+        let start = Instant::now();
+        let ms = |ms| start + Duration::from_millis(ms);
+        let mut keys = ShiftKeys {
+            key_up_events: false,
+            held: None,
+        };
+        // A tap moves once and lets go before auto shift starts.
+        assert_eq!(
+            keys.key(-1, KeyEventKind::Press, ms(0)),
+            Some(Input::Shift(-1))
+        );
+        assert_eq!(keys.update(ms(100)), None);
+        assert_eq!(keys.update(ms(TAP_MS)), Some(Input::Shift(0)));
+        // A held key moves once more when the terminal starts repeating it, and then keeps
+        // the direction for as long as the repeats come.
+        keys.key(1, KeyEventKind::Press, ms(1000));
+        assert_eq!(keys.update(ms(1000 + TAP_MS)), Some(Input::Shift(0)));
+        assert_eq!(
+            keys.key(1, KeyEventKind::Press, ms(1300)),
+            Some(Input::Shift(1))
+        );
+        for t in (1330..2000).step_by(30) {
+            assert_eq!(keys.key(1, KeyEventKind::Press, ms(t)), None);
+            assert_eq!(keys.update(ms(t + 20)), None);
+        }
+        assert_eq!(keys.update(ms(1990 + REPEAT_MS)), Some(Input::Shift(0)));
+        // The other direction takes over straight away.
+        keys.key(1, KeyEventKind::Press, ms(3000));
+        assert_eq!(
+            keys.key(-1, KeyEventKind::Press, ms(3010)),
+            Some(Input::Shift(-1))
+        );
+    }
+
+    #[test]
+    fn test_shift_with_key_up() {
+        let now = Instant::now();
+        let mut keys = ShiftKeys {
+            key_up_events: true,
+            held: None,
+        };
+        keys.key(-1, KeyEventKind::Press, now);
+        assert_eq!(keys.update(now + Duration::from_secs(5)), None);
+        assert_eq!(keys.key(1, KeyEventKind::Release, now), None);
+        assert_eq!(
+            keys.key(-1, KeyEventKind::Release, now),
+            Some(Input::Shift(0))
+        );
+    }
+}